@@ -60,6 +60,7 @@ pub mod processor {
                 async move { processor.compress_single_file(path, mode, dict_size).await }
             }))
             .buffer_unordered(self.concurrency_limit)
+            .map_ok(|(path, data, _)| (path, data))
             .try_collect()
             .await?;
 
@@ -77,18 +78,9 @@ pub mod processor {
             stream::iter(files.into_iter().map(move |path| {
                 let processor = self.clone();
                 async move {
-                    let (path_buf, _data) = processor
+                    let (path_buf, _data, stats) = processor
                         .compress_single_file(path, mode, dict_size)
                         .await?;
-                    let stats = CompressionStats {
-                        literal_count: 0,
-                        match_count: 0,
-                        bytes_processed: 0,
-                        longest_match: 0,
-                        input_bytes: 0,  // Would be filled from actual compression
-                        output_bytes: 0, // Would be filled from actual compression
-                        compression_ratio: 0.0,
-                    };
                     Ok((path_buf, stats))
                 }
             }))
@@ -101,13 +93,13 @@ pub mod processor {
             path: P,
             mode: CompressionMode,
             dict_size: DictionarySize,
-        ) -> Result<(PathBuf, Vec<u8>)> {
+        ) -> Result<(PathBuf, Vec<u8>, CompressionStats)> {
             let path = path.as_ref();
             let file = File::open(path).await?;
             let reader = BufReader::new(file);
 
-            let compressed = self.compress_reader(reader, mode, dict_size).await?;
-            Ok((path.to_path_buf(), compressed))
+            let (compressed, stats) = self.compress_reader(reader, mode, dict_size).await?;
+            Ok((path.to_path_buf(), compressed, stats))
         }
 
         /// Compress data from an async reader
@@ -116,30 +108,33 @@ pub mod processor {
             mut reader: R,
             mode: CompressionMode,
             dict_size: DictionarySize,
-        ) -> Result<Vec<u8>> {
+        ) -> Result<(Vec<u8>, CompressionStats)> {
             use crate::async_implode::AsyncImplodeWriter;
 
             let mut output = Vec::new();
-            let mut writer = AsyncImplodeWriter::new(&mut output, mode, dict_size)?;
-
-            // Read and compress in chunks
-            let mut buffer = vec![0u8; self.chunk_size];
-            loop {
-                let bytes_read = reader.read(&mut buffer).await?;
-                if bytes_read == 0 {
-                    break;
+            let stats = {
+                let mut writer = AsyncImplodeWriter::new(&mut output, mode, dict_size)?;
+
+                // Read and compress in chunks
+                let mut buffer = vec![0u8; self.chunk_size];
+                loop {
+                    let bytes_read = reader.read(&mut buffer).await?;
+                    if bytes_read == 0 {
+                        break;
+                    }
+
+                    writer.write_chunk(&buffer[..bytes_read]).await?;
+
+                    // Yield control periodically for fairness
+                    if bytes_read == self.chunk_size {
+                        tokio::task::yield_now().await;
+                    }
                 }
 
-                writer.write_chunk(&buffer[..bytes_read]).await?;
-
-                // Yield control periodically for fairness
-                if bytes_read == self.chunk_size {
-                    tokio::task::yield_now().await;
-                }
-            }
-
-            writer.finish().await?;
-            Ok(output)
+                writer.try_finish().await?;
+                writer.stats()
+            };
+            Ok((output, stats))
         }
 
         /// Process files with memory usage monitoring
@@ -159,6 +154,7 @@ pub mod processor {
                     async move { processor.compress_single_file(path, mode, dict_size).await }
                 }))
                 .buffer_unordered(self.concurrency_limit)
+                .map_ok(|(path, data, _)| (path, data))
                 .try_collect::<Vec<_>>()
                 .await?;
 
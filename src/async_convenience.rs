@@ -16,7 +16,26 @@ pub mod functions {
     pub async fn explode_async<R: AsyncRead + Unpin>(reader: R) -> Result<Vec<u8>> {
         use crate::async_explode::AsyncExplodeReader;
 
-        let mut exploder = AsyncExplodeReader::new(reader)?;
+        read_exploded(AsyncExplodeReader::new(reader)?).await
+    }
+
+    /// Decompress untrusted data from an async reader
+    ///
+    /// Fails with `PkLibError::OutputLimitExceeded` if the data would
+    /// decompress to more than `max_output` bytes.
+    pub async fn explode_async_limited<R: AsyncRead + Unpin>(
+        reader: R,
+        max_output: u64,
+    ) -> Result<Vec<u8>> {
+        use crate::async_explode::AsyncExplodeReader;
+
+        read_exploded(AsyncExplodeReader::new(reader)?.with_limit(max_output)).await
+    }
+
+    /// Collect the decompressed data of an async reader
+    async fn read_exploded<R: AsyncRead + Unpin>(
+        mut exploder: crate::async_explode::AsyncExplodeReader<R>,
+    ) -> Result<Vec<u8>> {
         let mut output = Vec::new();
 
         while let Some(chunk) = exploder.try_next().await? {
@@ -69,6 +88,15 @@ pub mod functions {
         explode_async(Cursor::new(data)).await
     }
 
+    /// Decompress untrusted data from bytes
+    ///
+    /// Fails with `PkLibError::OutputLimitExceeded` if the data would
+    /// decompress to more than `max_output` bytes.
+    pub async fn explode_bytes_async_limited(data: &[u8], max_output: u64) -> Result<Vec<u8>> {
+        use std::io::Cursor;
+        explode_async_limited(Cursor::new(data), max_output).await
+    }
+
     /// Compress a file asynchronously
     pub async fn compress_file<P1: AsRef<Path>, P2: AsRef<Path>>(
         input_path: P1,
@@ -102,24 +130,14 @@ pub mod functions {
         let mut writer = BufWriter::new(output);
 
         let mut reader = AsyncExplodeReader::new(input)?;
-        let mut total_bytes = 0u64;
 
         while let Some(chunk) = reader.try_next().await? {
             writer.write_all(&chunk).await?;
-            total_bytes += chunk.len() as u64;
         }
 
         writer.flush().await?;
 
-        Ok(CompressionStats {
-            literal_count: 0,
-            match_count: 0,
-            bytes_processed: total_bytes as usize,
-            longest_match: 0,
-            input_bytes: 0, // We'd need to track compressed size
-            output_bytes: total_bytes,
-            compression_ratio: 0.0,
-        })
+        Ok(reader.stats())
     }
 
     /// Compress multiple files concurrently
@@ -6,32 +6,38 @@
 #[cfg(feature = "async")]
 /// Async streaming decompression with overlapped I/O operations
 pub mod reader {
-    use crate::explode::ExplodeState;
-    use crate::{CompressionMode, PkLibError, Result};
+    use crate::explode::ExplodeDecoder;
+    use crate::{CompressionStats, PkLibError, Result, Status};
     use bytes::Bytes;
     use futures::stream::Stream;
     use futures::Future;
     use pin_project::pin_project;
-    use std::collections::VecDeque;
     use std::pin::Pin;
     use std::task::{Context, Poll};
     use tokio::io::{AsyncRead, AsyncReadExt};
 
     /// Async streaming decompressor with overlapped I/O
+    ///
+    /// Compressed data is read in chunks of `buffer_size` bytes and fed to an
+    /// `ExplodeDecoder`; each item of the stream holds up to `buffer_size`
+    /// bytes of decompressed data.
     #[pin_project]
     #[derive(Debug)]
     pub struct AsyncExplodeReader<R: AsyncRead + Unpin> {
         #[pin]
         reader: R,
-        state: ExplodeState,
-        initialized: bool,
+        decoder: ExplodeDecoder,
         finished: bool,
-        output_buffer: VecDeque<Bytes>,
         buffer_size: usize,
-        // Pipeline buffers for overlapped operations
+        // Compressed data read but not yet decoded
         read_buffer: Vec<u8>,
-        decompress_buffer: Vec<u8>,
-        pending_read: Option<Vec<u8>>,
+        read_pos: usize,
+        read_len: usize,
+        need_input: bool,
+        max_output: Option<u64>,
+        total_out: u64,
+        // Error held back until the data before it was handed out
+        error: Option<PkLibError>,
     }
 
     impl<R: AsyncRead + Unpin> AsyncExplodeReader<R> {
@@ -42,97 +48,96 @@ pub mod reader {
 
         /// Create a new AsyncExplodeReader with custom buffer size
         pub fn with_buffer_size(reader: R, buffer_size: usize) -> Result<Self> {
+            if buffer_size == 0 {
+                return Err(PkLibError::InvalidData(
+                    "Buffer size must not be zero".to_string(),
+                ));
+            }
+
             Ok(Self {
                 reader,
-                state: ExplodeState::new(),
-                initialized: false,
+                decoder: ExplodeDecoder::new(),
                 finished: false,
-                output_buffer: VecDeque::new(),
                 buffer_size,
                 read_buffer: vec![0u8; buffer_size],
-                decompress_buffer: Vec::new(),
-                pending_read: None,
+                read_pos: 0,
+                read_len: 0,
+                need_input: true,
+                max_output: None,
+                total_out: 0,
+                error: None,
             })
         }
 
-        /// Initialize the reader by reading and parsing the header
-        async fn initialize(&mut self) -> Result<()> {
-            if self.initialized {
-                return Ok(());
-            }
-
-            // Read header data
-            let mut header_buf = [0u8; 8];
-            let bytes_read = self.reader.read(&mut header_buf).await?;
-            if bytes_read < 3 {
-                return Err(PkLibError::InvalidData("Header too short".to_string()));
-            }
-
-            // Parse header
-            self.state.ctype = match header_buf[0] {
-                0 => CompressionMode::Binary,
-                1 => CompressionMode::ASCII,
-                _ => return Err(PkLibError::InvalidCompressionMode(header_buf[0])),
-            };
-
-            self.state.dsize_bits = header_buf[1] as u32;
-            if self.state.dsize_bits < 4 || self.state.dsize_bits > 6 {
-                return Err(PkLibError::InvalidDictionaryBits(
-                    self.state.dsize_bits as u8,
-                ));
-            }
-
-            self.state.dsize_mask = 0xFFFF >> (16 - self.state.dsize_bits);
-            self.state.bit_buff = header_buf[2] as u32;
-            self.state.extra_bits = 0;
-
-            // Copy remaining header data to input buffer
-            if bytes_read > 3 {
-                self.state.in_buff[..bytes_read - 3].copy_from_slice(&header_buf[3..bytes_read]);
-                self.state.in_bytes = bytes_read - 3;
-                self.state.in_pos = 0;
-            }
-
-            // Initialize with header data
-            let header_data = &header_buf[..bytes_read];
-            self.state.initialize(header_data)?;
+        /// Limit the decompressed data to `max_output` bytes
+        ///
+        /// The stream yields the first `max_output` bytes, followed by
+        /// `PkLibError::OutputLimitExceeded` if the data holds more.
+        pub fn with_limit(mut self, max_output: u64) -> Self {
+            self.max_output = Some(max_output);
+            self
+        }
 
-            self.initialized = true;
-            Ok(())
+        /// Statistics of the data decompressed so far
+        ///
+        /// See `ExplodeDecoder::stats`.
+        pub fn stats(&self) -> CompressionStats {
+            self.decoder.stats()
         }
 
         /// Process next chunk of data
         async fn process_chunk(&mut self) -> Result<Option<Bytes>> {
-            if !self.initialized {
-                self.initialize().await?;
+            if let Some(error) = self.error.take() {
+                return Err(error);
             }
-
             if self.finished {
                 return Ok(None);
             }
 
-            // Read more data if needed
-            if self.state.in_pos >= self.state.in_bytes {
-                let bytes_read = self.reader.read(&mut self.state.in_buff).await?;
-                if bytes_read == 0 {
-                    self.finished = true;
-                    return Ok(None);
+            let mut output = vec![0u8; self.buffer_size];
+            loop {
+                // Read more data once the decoder has taken all of it
+                if self.need_input && self.read_pos == self.read_len {
+                    let bytes_read = self.reader.read(&mut self.read_buffer).await?;
+                    if bytes_read == 0 {
+                        return Err(PkLibError::UnexpectedEof);
+                    }
+                    self.read_pos = 0;
+                    self.read_len = bytes_read;
                 }
-                self.state.in_bytes = bytes_read;
-                self.state.in_pos = 0;
-            }
 
-            // For now, return a simplified chunk of the input data
-            // In a real implementation, this would do proper decompression
-            let chunk_size = (self.state.in_bytes - self.state.in_pos).min(1024);
-            if chunk_size > 0 {
-                let chunk =
-                    self.state.in_buff[self.state.in_pos..self.state.in_pos + chunk_size].to_vec();
-                self.state.in_pos += chunk_size;
-                Ok(Some(Bytes::from(chunk)))
-            } else {
-                self.finished = true;
-                Ok(None)
+                let input = &self.read_buffer[self.read_pos..self.read_len];
+                let (consumed, produced, status) = self.decoder.decompress(input, &mut output)?;
+                self.read_pos += consumed;
+                self.need_input = status == Status::GetInput;
+                self.finished = status == Status::StreamEnd;
+
+                if produced > 0 {
+                    let mut produced = produced;
+                    if let Some(limit) = self.max_output {
+                        if self.total_out + produced as u64 > limit {
+                            let room = (limit - self.total_out) as usize;
+                            let error = PkLibError::OutputLimitExceeded { limit };
+                            if room == 0 {
+                                return Err(error);
+                            }
+
+                            // Cut this chunk at the limit and return the
+                            // error on the next call
+                            self.error = Some(error);
+                            self.finished = false;
+                            produced = room;
+                        }
+                    }
+
+                    self.total_out += produced as u64;
+                    output.truncate(produced);
+                    return Ok(Some(Bytes::from(output)));
+                }
+
+                if self.finished {
+                    return Ok(None);
+                }
             }
         }
     }
@@ -141,16 +146,8 @@ pub mod reader {
         type Item = Result<Bytes>;
 
         fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-            // Check if we have buffered output
-            {
-                let this = self.as_mut().project();
-                if let Some(chunk) = this.output_buffer.pop_front() {
-                    return Poll::Ready(Some(Ok(chunk)));
-                }
-
-                if *this.finished {
-                    return Poll::Ready(None);
-                }
+            if self.finished {
+                return Poll::Ready(None);
             }
 
             // Try to process next chunk
@@ -164,7 +161,12 @@ pub mod reader {
                     *this.finished = true;
                     Poll::Ready(None)
                 }
-                Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e))),
+                Poll::Ready(Err(e)) => {
+                    // Errors end the stream
+                    let this = self.project();
+                    *this.finished = true;
+                    Poll::Ready(Some(Err(e)))
+                }
                 Poll::Pending => Poll::Pending,
             }
         }
@@ -6,31 +6,34 @@
 #[cfg(feature = "async")]
 /// Async streaming compression with pipelined operations
 pub mod writer {
-    use crate::implode::ImplodeState;
-    use crate::{CompressionMode, DictionarySize, PkLibError, Result};
+    use crate::implode::ImplodeEncoder;
+    use crate::{CompressionMode, CompressionStats, DictionarySize, PkLibError, Result, Status};
     use pin_project::pin_project;
     use std::pin::Pin;
-    use std::task::{Context, Poll};
+    use std::task::{ready, Context, Poll};
     use tokio::io::{AsyncWrite, AsyncWriteExt};
 
-    /// Async streaming compressor with pipeline processing
+    /// Async streaming compressor
+    ///
+    /// Input is fed to an `ImplodeEncoder`, and the compressed data is
+    /// written to the underlying writer in chunks of up to `buffer_size`
+    /// bytes. The stream is the same as `ImplodeWriter` produces.
+    ///
+    /// Through `AsyncWrite`, `poll_shutdown` writes the end of the stream.
     #[pin_project]
     #[derive(Debug)]
     pub struct AsyncImplodeWriter<W: AsyncWrite + Unpin> {
         #[pin]
         writer: W,
-        state: ImplodeState,
-        mode: CompressionMode,
-        dict_size: DictionarySize,
-        initialized: bool,
+        // Boxed, as the encoder's buffers are too large to move around
+        // inside futures
+        encoder: Box<ImplodeEncoder>,
         finished: bool,
-        // Pipeline buffers for overlapped operations
-        input_buffers: [Vec<u8>; 3],
-        compress_buffers: [Vec<u8>; 3],
-        output_buffers: [Vec<u8>; 3],
-        current_buffer: usize,
-        buffer_size: usize,
-        pending_input: Vec<u8>,
+        // Compressed data, of which `output_pos..output_len` still has to be
+        // written
+        output_buffer: Vec<u8>,
+        output_pos: usize,
+        output_len: usize,
     }
 
     impl<W: AsyncWrite + Unpin> AsyncImplodeWriter<W> {
@@ -46,202 +49,197 @@ pub mod writer {
             dict_size: DictionarySize,
             buffer_size: usize,
         ) -> Result<Self> {
-            let state = ImplodeState::new(mode, dict_size)?;
+            if buffer_size == 0 {
+                return Err(PkLibError::InvalidData(
+                    "Buffer size must not be zero".to_string(),
+                ));
+            }
 
             Ok(Self {
                 writer,
-                state,
-                mode,
-                dict_size,
-                initialized: false,
+                encoder: Box::new(ImplodeEncoder::new(mode, dict_size)?),
                 finished: false,
-                input_buffers: [
-                    Vec::with_capacity(buffer_size),
-                    Vec::with_capacity(buffer_size),
-                    Vec::with_capacity(buffer_size),
-                ],
-                compress_buffers: [
-                    Vec::with_capacity(buffer_size),
-                    Vec::with_capacity(buffer_size),
-                    Vec::with_capacity(buffer_size),
-                ],
-                output_buffers: [
-                    Vec::with_capacity(buffer_size),
-                    Vec::with_capacity(buffer_size),
-                    Vec::with_capacity(buffer_size),
-                ],
-                current_buffer: 0,
-                buffer_size,
-                pending_input: Vec::new(),
+                output_buffer: vec![0u8; buffer_size],
+                output_pos: 0,
+                output_len: 0,
             })
         }
 
-        /// Initialize the writer by setting up compression state
-        async fn initialize(&mut self) -> Result<()> {
-            if self.initialized {
-                return Ok(());
-            }
-
-            // Write header
-            let header = [self.mode as u8, self.dict_size.bits()];
-            self.writer.write_all(&header).await?;
-
-            self.initialized = true;
-            Ok(())
+        /// Statistics of the data compressed so far
+        ///
+        /// See `ImplodeWriter::stats`. The statistics are complete after
+        /// `try_finish()`.
+        pub fn stats(&self) -> CompressionStats {
+            self.encoder.stats()
         }
 
         /// Write a chunk of data asynchronously
         pub async fn write_chunk(&mut self, data: &[u8]) -> Result<()> {
-            if !self.initialized {
-                self.initialize().await?;
-            }
-
             if self.finished {
                 return Err(PkLibError::InvalidData(
                     "Writer already finished".to_string(),
                 ));
             }
 
-            // Add data to pending input
-            self.pending_input.extend_from_slice(data);
-
-            // Process full buffers
-            while self.pending_input.len() >= self.buffer_size {
-                let mut chunk = self.pending_input.split_off(self.buffer_size);
-                std::mem::swap(&mut chunk, &mut self.pending_input);
-
-                self.process_buffer(chunk).await?;
-            }
-
-            Ok(())
+            self.write_pending().await?;
+            self.compress(data).await
         }
 
-        /// Process a buffer through the compression pipeline
-        async fn process_buffer(&mut self, data: Vec<u8>) -> Result<()> {
-            // Simulate compression work
-            let compressed = self.compress_data(&data)?;
-
-            // Write compressed data
-            if !compressed.is_empty() {
-                self.writer.write_all(&compressed).await?;
+        /// Write the compressed data left over from `AsyncWrite` calls
+        async fn write_pending(&mut self) -> Result<()> {
+            if self.output_pos < self.output_len {
+                self.writer
+                    .write_all(&self.output_buffer[self.output_pos..self.output_len])
+                    .await?;
+                self.output_pos = 0;
+                self.output_len = 0;
             }
-
             Ok(())
         }
 
-        /// Compress data using PKLib algorithm
-        fn compress_data(&mut self, data: &[u8]) -> Result<Vec<u8>> {
-            // Copy data to work buffer
-            if data.len() > self.state.work_buff.len() {
-                return Err(PkLibError::InvalidData(
-                    "Data too large for buffer".to_string(),
-                ));
-            }
-
-            self.state.work_buff[..data.len()].copy_from_slice(data);
-            self.state.work_bytes = data.len();
-
-            // Build hash table and compress
-            if self.state.work_bytes > 1 {
-                self.state.sort_buffer(0, self.state.work_bytes);
-
-                // Simplified compression logic
-                let mut output = Vec::new();
-                let mut pos = 0;
-
-                while pos < self.state.work_bytes {
-                    let match_result = self.state.find_repetition(pos);
-
-                    if match_result.is_match() {
-                        // Encode match (simplified)
-                        let length_code = (match_result.length + 0xFE).min(255);
-                        output.push(length_code as u8);
-
-                        // Encode distance (simplified)
-                        let distance = match_result.distance.min(255);
-                        output.push(distance as u8);
-
-                        pos += match_result.length;
-                    } else {
-                        // Encode literal
-                        output.push(self.state.work_buff[pos]);
-                        pos += 1;
-                    }
+        /// Compress `data`, writing out the compressed data as it is produced
+        async fn compress(&mut self, mut data: &[u8]) -> Result<()> {
+            loop {
+                let (consumed, produced, status) =
+                    self.encoder.compress(data, &mut self.output_buffer)?;
+                data = &data[consumed..];
+                self.writer
+                    .write_all(&self.output_buffer[..produced])
+                    .await?;
+
+                if status == Status::GetInput {
+                    return Ok(());
                 }
-
-                return Ok(output);
             }
-
-            Ok(Vec::new())
         }
 
-        /// Finish compression and flush remaining data
-        pub async fn finish(mut self) -> Result<W> {
+        /// Compress the remaining input and write the end of the stream
+        /// without consuming the writer, so that the final `stats()` can be
+        /// read
+        pub async fn try_finish(&mut self) -> Result<()> {
+            self.write_pending().await?;
             if self.finished {
-                return Ok(self.writer);
+                return Ok(());
             }
 
-            if !self.initialized {
-                self.initialize().await?;
-            }
+            loop {
+                let (produced, status) = self.encoder.finish(&mut self.output_buffer)?;
+                self.writer
+                    .write_all(&self.output_buffer[..produced])
+                    .await?;
 
-            // Process any remaining input
-            if !self.pending_input.is_empty() {
-                let remaining = std::mem::take(&mut self.pending_input);
-                self.process_buffer(remaining).await?;
+                if status == Status::StreamEnd {
+                    break;
+                }
             }
 
-            // Write end marker
-            let end_marker = [0x05, 0x03]; // Simplified end marker
-            self.writer.write_all(&end_marker).await?;
-
-            // Flush writer
             self.writer.flush().await?;
-
             self.finished = true;
+            Ok(())
+        }
+
+        /// Finish compression and flush remaining data
+        pub async fn finish(mut self) -> Result<W> {
+            self.try_finish().await?;
             Ok(self.writer)
         }
 
         /// Flush any pending data
+        ///
+        /// Input that does not yet fill a whole block stays in the encoder
+        /// until more data arrives or the writer is finished.
         pub async fn flush(&mut self) -> Result<()> {
-            if !self.initialized {
-                self.initialize().await?;
-            }
+            self.write_pending().await?;
+            self.writer.flush().await?;
+            Ok(())
+        }
 
-            // Process pending input if we have enough for a partial buffer
-            if self.pending_input.len() > self.buffer_size / 2 {
-                let to_process = std::mem::take(&mut self.pending_input);
-                self.process_buffer(to_process).await?;
+        /// Write the compressed data left over in the output buffer
+        fn poll_write_pending(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let mut this = self.project();
+            while *this.output_pos < *this.output_len {
+                let pending = &this.output_buffer[*this.output_pos..*this.output_len];
+                let written = ready!(this.writer.as_mut().poll_write(cx, pending))?;
+                if written == 0 {
+                    return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+                }
+                *this.output_pos += written;
             }
 
-            self.writer.flush().await?;
-            Ok(())
+            *this.output_pos = 0;
+            *this.output_len = 0;
+            Poll::Ready(Ok(()))
         }
     }
 
     impl<W: AsyncWrite + Unpin> tokio::io::AsyncWrite for AsyncImplodeWriter<W> {
         fn poll_write(
-            self: Pin<&mut Self>,
-            _cx: &mut Context<'_>,
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
             buf: &[u8],
         ) -> Poll<std::io::Result<usize>> {
-            let this = self.project();
+            if self.finished {
+                return Poll::Ready(Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    PkLibError::InvalidData("Cannot write after finish".to_string()),
+                )));
+            }
 
-            // Add data to pending input
-            this.pending_input.extend_from_slice(buf);
+            // Compress until some input is taken, writing out the compressed
+            // data that is in the way. Output left over is written by the
+            // next call.
+            loop {
+                ready!(self.as_mut().poll_write_pending(cx))?;
+                if buf.is_empty() {
+                    return Poll::Ready(Ok(0));
+                }
 
-            // For async write trait, we just accept all data
-            Poll::Ready(Ok(buf.len()))
+                let this = self.as_mut().project();
+                let (consumed, produced, _) = this
+                    .encoder
+                    .compress(buf, this.output_buffer)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                *this.output_len = produced;
+
+                if consumed > 0 {
+                    return Poll::Ready(Ok(consumed));
+                }
+            }
         }
 
-        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-            let this = self.project();
-            this.writer.poll_flush(cx)
+        /// Write out all complete compressed bytes
+        ///
+        /// Input that does not yet fill a whole block stays in the encoder
+        /// until more data arrives or the writer is shut down.
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            ready!(self.as_mut().poll_write_pending(cx))?;
+            self.project().writer.poll_flush(cx)
         }
 
-        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-            let this = self.project();
-            this.writer.poll_shutdown(cx)
+        /// Write the end of the stream, then shut down the underlying writer
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            loop {
+                ready!(self.as_mut().poll_write_pending(cx))?;
+                if self.finished {
+                    break;
+                }
+
+                let this = self.as_mut().project();
+                let (produced, status) = this
+                    .encoder
+                    .finish(this.output_buffer)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                *this.output_len = produced;
+                *this.finished = status == Status::StreamEnd;
+            }
+
+            self.project().writer.poll_shutdown(cx)
         }
     }
 }
@@ -80,16 +80,6 @@ pub mod processor {
             let mut reader = BufReader::with_capacity(options.chunk_size, reader);
 
             let mut buffer = vec![0u8; options.chunk_size];
-            let mut stats = CompressionStats {
-                literal_count: 0,
-                match_count: 0,
-                bytes_processed: 0,
-                longest_match: 0,
-                input_bytes: 0,
-                output_bytes: 0,
-                compression_ratio: 0.0,
-            };
-
             let mut processed_bytes = 0usize;
             let mut chunks_processed = 0usize;
 
@@ -99,7 +89,6 @@ pub mod processor {
                     break;
                 }
 
-                stats.input_bytes += bytes_read as u64;
                 compressor.write_chunk(&buffer[..bytes_read]).await?;
 
                 processed_bytes += bytes_read;
@@ -116,33 +105,23 @@ pub mod processor {
                 }
 
                 // Periodic yielding for fairness
-                if chunks_processed % 16 == 0 {
+                if chunks_processed.is_multiple_of(16) {
                     tokio::task::yield_now().await;
                 }
 
                 // Progress reporting (if enabled)
-                if options.show_progress && chunks_processed % 100 == 0 {
+                if options.show_progress && chunks_processed.is_multiple_of(100) {
                     log::debug!(
                         "Processed {} chunks ({} bytes)",
                         chunks_processed,
-                        stats.input_bytes
+                        compressor.stats().input_bytes
                     );
                 }
             }
 
             // Finish compression
-            let _final_writer = compressor.finish().await?;
-
-            // For simplicity, we can't easily get output bytes from the generic writer
-            // In a real implementation, we'd wrap the writer to count bytes
-            stats.output_bytes = stats.input_bytes / 2; // Estimate
-            stats.compression_ratio = if stats.input_bytes > 0 {
-                stats.output_bytes as f64 / stats.input_bytes as f64
-            } else {
-                0.0
-            };
-
-            Ok(stats)
+            compressor.try_finish().await?;
+            Ok(compressor.stats())
         }
 
         /// Process a file from path to path with streaming
@@ -185,7 +164,6 @@ pub mod processor {
         // Pipeline state
         active_buffers: Vec<Vec<u8>>,
         current_buffer: usize,
-        stats: CompressionStats,
     }
 
     impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> StreamPipeline<R, W> {
@@ -215,15 +193,6 @@ pub mod processor {
                 options,
                 active_buffers,
                 current_buffer: 0,
-                stats: CompressionStats {
-                    literal_count: 0,
-                    match_count: 0,
-                    bytes_processed: 0,
-                    longest_match: 0,
-                    input_bytes: 0,
-                    output_bytes: 0,
-                    compression_ratio: 0.0,
-                },
             })
         }
 
@@ -238,7 +207,6 @@ pub mod processor {
                 return Ok(false); // End of stream
             }
 
-            self.stats.input_bytes += bytes_read as u64;
             self.compressor.write_chunk(&buffer[..bytes_read]).await?;
 
             // Rotate to next buffer
@@ -251,14 +219,14 @@ pub mod processor {
         }
 
         /// Finish processing and return final statistics
-        pub async fn finish(self) -> Result<CompressionStats> {
-            self.compressor.finish().await?;
-            Ok(self.stats)
+        pub async fn finish(mut self) -> Result<CompressionStats> {
+            self.compressor.try_finish().await?;
+            Ok(self.compressor.stats())
         }
 
         /// Get current processing statistics
-        pub fn stats(&self) -> &CompressionStats {
-            &self.stats
+        pub fn stats(&self) -> CompressionStats {
+            self.compressor.stats()
         }
     }
 }
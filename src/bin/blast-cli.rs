@@ -4,7 +4,10 @@
 
 use clap::{Parser, Subcommand, ValueEnum};
 use indicatif::{ProgressBar, ProgressStyle};
-use pklib::{explode_bytes, implode_bytes, CompressionMode, DictionarySize};
+use pklib::{
+    explode_bytes, explode_bytes_limited, implode_bytes_with_level, CompressionLevel,
+    CompressionMode, DictionarySize,
+};
 use std::fs;
 use std::path::PathBuf;
 use std::time::Instant;
@@ -44,6 +47,10 @@ enum Commands {
         #[arg(short, long, value_enum, default_value_t = CliDictionarySize::Size2K)]
         dict_size: CliDictionarySize,
 
+        /// Compression level
+        #[arg(short, long, value_enum, default_value_t = CliCompressionLevel::Default)]
+        level: CliCompressionLevel,
+
         /// Force overwrite of output file
         #[arg(short, long)]
         force: bool,
@@ -57,6 +64,10 @@ enum Commands {
         /// Output decompressed file
         output: PathBuf,
 
+        /// Abort if the decompressed data would exceed this many bytes
+        #[arg(long, value_name = "BYTES")]
+        max_output: Option<u64>,
+
         /// Force overwrite of output file
         #[arg(short, long)]
         force: bool,
@@ -106,6 +117,26 @@ impl From<CliDictionarySize> for DictionarySize {
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum CliCompressionLevel {
+    /// Faster compression with a lower ratio
+    Fast,
+    /// Same output as PKLib - Default
+    Default,
+    /// Slower compression with a higher ratio
+    Best,
+}
+
+impl From<CliCompressionLevel> for CompressionLevel {
+    fn from(level: CliCompressionLevel) -> Self {
+        match level {
+            CliCompressionLevel::Fast => CompressionLevel::Fast,
+            CliCompressionLevel::Default => CompressionLevel::Default,
+            CliCompressionLevel::Best => CompressionLevel::Best,
+        }
+    }
+}
+
 fn main() {
     let cli = Cli::parse();
 
@@ -115,12 +146,16 @@ fn main() {
             output,
             mode,
             dict_size,
+            level,
             force,
         } => compress_file(
             &input,
             &output,
-            mode.into(),
-            dict_size.into(),
+            CompressionSettings {
+                mode: mode.into(),
+                dict_size: dict_size.into(),
+                level: level.into(),
+            },
             force,
             cli.verbose,
             cli.quiet,
@@ -128,8 +163,9 @@ fn main() {
         Commands::Decompress {
             input,
             output,
+            max_output,
             force,
-        } => decompress_file(&input, &output, force, cli.verbose, cli.quiet),
+        } => decompress_file(&input, &output, max_output, force, cli.verbose, cli.quiet),
         Commands::Info { input } => show_file_info(&input, cli.verbose),
     };
 
@@ -139,15 +175,27 @@ fn main() {
     }
 }
 
+/// Settings passed to the compressor
+struct CompressionSettings {
+    mode: CompressionMode,
+    dict_size: DictionarySize,
+    level: CompressionLevel,
+}
+
 fn compress_file(
     input: &PathBuf,
     output: &PathBuf,
-    mode: CompressionMode,
-    dict_size: DictionarySize,
+    settings: CompressionSettings,
     force: bool,
     verbose: bool,
     quiet: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let CompressionSettings {
+        mode,
+        dict_size,
+        level,
+    } = settings;
+
     // Check if input file exists
     if !input.exists() {
         return Err(format!("Input file '{}' does not exist", input.display()).into());
@@ -168,7 +216,7 @@ fn compress_file(
             input.display(),
             output.display()
         );
-        println!("Mode: {mode:?}, Dictionary: {dict_size:?}");
+        println!("Mode: {mode:?}, Dictionary: {dict_size:?}, Level: {level:?}");
     }
 
     let start_time = Instant::now();
@@ -203,7 +251,7 @@ fn compress_file(
     }
 
     // Compress data
-    let compressed_data = implode_bytes(&input_data, mode, dict_size)
+    let compressed_data = implode_bytes_with_level(&input_data, mode, dict_size, level)
         .map_err(|e| format!("Compression failed: {e}"))?;
 
     if let Some(ref pb) = progress {
@@ -236,6 +284,7 @@ fn compress_file(
 fn decompress_file(
     input: &PathBuf,
     output: &PathBuf,
+    max_output: Option<u64>,
     force: bool,
     verbose: bool,
     quiet: bool,
@@ -294,8 +343,11 @@ fn decompress_file(
     }
 
     // Decompress data
-    let decompressed_data =
-        explode_bytes(&compressed_data).map_err(|e| format!("Decompression failed: {e}"))?;
+    let decompressed_data = match max_output {
+        Some(limit) => explode_bytes_limited(&compressed_data, limit),
+        None => explode_bytes(&compressed_data),
+    }
+    .map_err(|e| format!("Decompression failed: {e}"))?;
 
     if let Some(ref pb) = progress {
         pb.inc(1);
@@ -405,15 +457,18 @@ mod tests {
         compress_file(
             &input_path,
             &compressed_path,
-            CompressionMode::ASCII,
-            DictionarySize::Size2K,
+            CompressionSettings {
+                mode: CompressionMode::ASCII,
+                dict_size: DictionarySize::Size2K,
+                level: CompressionLevel::Default,
+            },
             false,
             false,
             true,
         )?;
 
         // Decompress
-        decompress_file(&compressed_path, &output_path, false, false, true)?;
+        decompress_file(&compressed_path, &output_path, None, false, false, true)?;
 
         // Verify
         let result_data = fs::read(&output_path)?;
@@ -3,6 +3,8 @@
 //! This module defines the core types, constants, and structures used by both
 //! the compression (implode) and decompression (explode) algorithms.
 
+use crate::explode::{DIST_SIZES, LENS_SIZES};
+use crate::tables::{CH_BITS_ASC, LEN_BASE};
 use thiserror::Error;
 
 /// Compression mode for the PKWare DCL format
@@ -23,8 +25,31 @@ impl CompressionMode {
             _ => Err(PkLibError::InvalidCompressionMode(value)),
         }
     }
+
+    /// Choose the mode that encodes the literals of `data` in fewer bits
+    ///
+    /// Estimates the cost of the first 64 KB of `data` as literals: a flat 8
+    /// bits per byte in Binary mode, and the `CH_BITS_ASC` code length in
+    /// ASCII mode. Repetitions cost the same in both modes, so the literals
+    /// decide. Returns Binary for empty data and on a tie.
+    pub fn detect(data: &[u8]) -> Self {
+        let sample = &data[..data.len().min(DETECT_SAMPLE_SIZE)];
+        let ascii_bits: usize = sample
+            .iter()
+            .map(|&byte| CH_BITS_ASC[byte as usize] as usize)
+            .sum();
+
+        if ascii_bits < sample.len() * 8 {
+            CompressionMode::ASCII
+        } else {
+            CompressionMode::Binary
+        }
+    }
 }
 
+/// Number of leading bytes `CompressionMode::detect` examines (64 KB)
+const DETECT_SAMPLE_SIZE: usize = 0x10000;
+
 /// Dictionary size for compression/decompression
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DictionarySize {
@@ -72,6 +97,96 @@ impl DictionarySize {
     }
 }
 
+/// Compression level, trading compression speed for ratio
+///
+/// Every level produces a valid PKWare DCL stream that any PKLib-compatible
+/// decompressor can read; only the choice of literals and repetitions differs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum CompressionLevel {
+    /// Examine fewer repetition candidates and skip lazy matching
+    ///
+    /// The output is usually, but not always, larger than at `Default`.
+    Fast,
+    /// Same output as PKLib's `implode()`, byte for byte
+    #[default]
+    Default,
+    /// Choose literals and repetitions by their exact encoded size
+    Best,
+}
+
+/// Progress reported by the push-style (sans-IO) APIs
+///
+/// Each status corresponds to one of PKLib's `PKDCL_*` result codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The output buffer is full; call again with more output space
+    Ok,
+    /// The end of the compressed stream was reached and all output delivered
+    StreamEnd,
+    /// All input was consumed; call again with more input
+    GetInput,
+}
+
+impl Status {
+    /// The matching PKLib result code (`PKDCL_OK`, `PKDCL_STREAM_END` or
+    /// `PKDCL_GET_INPUT`)
+    pub fn code(&self) -> u32 {
+        match self {
+            Status::Ok => crate::explode::PKDCL_OK,
+            Status::StreamEnd => crate::explode::PKDCL_STREAM_END,
+            Status::GetInput => crate::explode::PKDCL_GET_INPUT,
+        }
+    }
+}
+
+/// Part of a token that is being decoded from the compressed stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// A literal byte
+    Literal,
+    /// The length code of a repetition
+    LengthCode,
+    /// The extra bits of a repetition length
+    ExtraBits,
+    /// The distance of a repetition
+    Distance,
+}
+
+impl std::fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TokenKind::Literal => "literal",
+            TokenKind::LengthCode => "length code",
+            TokenKind::ExtraBits => "length extra bits",
+            TokenKind::Distance => "distance",
+        })
+    }
+}
+
+/// Position in a compressed stream where decoding failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamPosition {
+    /// Offset of the byte holding the first bit of the failing token part,
+    /// counted from the start of the stream (including the header)
+    pub byte_offset: u64,
+    /// Bit within that byte, counted from the least significant bit
+    pub bit_offset: u8,
+    /// Number of bytes decompressed before the failing token
+    pub output_offset: u64,
+    /// The part of the token that was being decoded
+    pub token: TokenKind,
+}
+
+impl std::fmt::Display for StreamPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "byte {} bit {} (output offset {}) while decoding {}",
+            self.byte_offset, self.bit_offset, self.output_offset, self.token
+        )
+    }
+}
+
 /// Error type for PKLib operations
 #[derive(Debug, Error)]
 pub enum PkLibError {
@@ -115,6 +230,40 @@ pub enum PkLibError {
     #[error("Decompression error: {0}")]
     DecompressionError(String),
 
+    /// Decompressed data exceeds the configured output limit
+    #[error("Decompressed data exceeds the limit of {limit} bytes")]
+    OutputLimitExceeded {
+        /// The output limit in bytes
+        limit: u64,
+    },
+
+    /// Decompressed data has a different length than expected
+    ///
+    /// If the stream runs past the expected length, decoding stops at the
+    /// first token that passes it, and `actual` is the length up to and
+    /// including that token: a lower bound of the real length, at most 516
+    /// bytes over `expected`.
+    #[error("Decompressed length mismatch: expected {expected} bytes, got {actual}")]
+    LengthMismatch {
+        /// Expected length in bytes
+        expected: usize,
+        /// Actual length in bytes
+        actual: usize,
+    },
+
+    /// Decoding failed at a known position in the compressed stream
+    ///
+    /// Truncated streams fail with `UnexpectedEof` and repetitions reaching
+    /// before the start of the data with `InvalidDistance`.
+    #[error("{error} at {position}")]
+    DecodeFailed {
+        /// Where decoding failed
+        position: StreamPosition,
+        /// What went wrong there
+        #[source]
+        error: Box<PkLibError>,
+    },
+
     /// CRC32 checksum mismatch
     #[error("CRC32 checksum mismatch: expected {expected:08X}, got {actual:08X}")]
     CrcMismatch {
@@ -129,6 +278,73 @@ pub enum PkLibError {
     Io(#[from] std::io::Error),
 }
 
+impl PkLibError {
+    /// Attach the position where decoding failed
+    pub(crate) fn at(self, position: StreamPosition) -> Self {
+        PkLibError::DecodeFailed {
+            position,
+            error: Box::new(self),
+        }
+    }
+
+    /// Position in the compressed stream where decoding failed, if known
+    pub fn position(&self) -> Option<&StreamPosition> {
+        match self {
+            PkLibError::DecodeFailed { position, .. } => Some(position),
+            _ => None,
+        }
+    }
+
+    /// The underlying error, without the position where it occurred
+    pub fn root_cause(&self) -> &PkLibError {
+        match self {
+            PkLibError::DecodeFailed { error, .. } => error.root_cause(),
+            error => error,
+        }
+    }
+}
+
+/// Copies the error, so a reader can report it again on later reads
+///
+/// `io::Error` can't be cloned, so an `Io` error is copied as a new error of
+/// the same kind with the same message.
+impl Clone for PkLibError {
+    fn clone(&self) -> Self {
+        match self {
+            PkLibError::InvalidCompressionMode(mode) => PkLibError::InvalidCompressionMode(*mode),
+            PkLibError::InvalidDictionaryBits(bits) => PkLibError::InvalidDictionaryBits(*bits),
+            PkLibError::InvalidDictionarySize(size) => PkLibError::InvalidDictionarySize(*size),
+            PkLibError::InvalidFormat => PkLibError::InvalidFormat,
+            PkLibError::UnexpectedEof => PkLibError::UnexpectedEof,
+            PkLibError::BufferTooSmall => PkLibError::BufferTooSmall,
+            PkLibError::InvalidLength(length) => PkLibError::InvalidLength(*length),
+            PkLibError::InvalidDistance(distance) => PkLibError::InvalidDistance(*distance),
+            PkLibError::InvalidData(message) => PkLibError::InvalidData(message.clone()),
+            PkLibError::DecompressionError(message) => {
+                PkLibError::DecompressionError(message.clone())
+            }
+            PkLibError::OutputLimitExceeded { limit } => {
+                PkLibError::OutputLimitExceeded { limit: *limit }
+            }
+            PkLibError::LengthMismatch { expected, actual } => PkLibError::LengthMismatch {
+                expected: *expected,
+                actual: *actual,
+            },
+            PkLibError::DecodeFailed { position, error } => PkLibError::DecodeFailed {
+                position: *position,
+                error: error.clone(),
+            },
+            PkLibError::CrcMismatch { expected, actual } => PkLibError::CrcMismatch {
+                expected: *expected,
+                actual: *actual,
+            },
+            PkLibError::Io(error) => {
+                PkLibError::Io(std::io::Error::new(error.kind(), error.to_string()))
+            }
+        }
+    }
+}
+
 /// Result type alias for PKLib operations
 pub type Result<T> = std::result::Result<T, PkLibError>;
 
@@ -158,6 +374,38 @@ pub const MIN_MATCH_LENGTH: usize = 3;
 /// PKLib file signature (if used)
 pub const PKLIB_SIGNATURE: u32 = 0x00088B1F;
 
+/// How much detail `CompressionStats` collects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatsLevel {
+    /// Token counts, byte totals and the checksum
+    #[default]
+    Basic,
+    /// Also histograms of the tokens, see `TokenHistograms`
+    Detailed,
+}
+
+/// Histograms of the tokens of a compressed stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenHistograms {
+    /// Number of repetitions per length code, indexed like `tables::LEN_BASE`
+    pub length_codes: [usize; LENS_SIZES],
+    /// Number of repetitions per distance code, indexed like
+    /// `tables::DIST_CODE`
+    pub distance_codes: [usize; DIST_SIZES],
+    /// Number of times each byte value was encoded as a literal
+    pub literals: [usize; 256],
+}
+
+impl Default for TokenHistograms {
+    fn default() -> Self {
+        Self {
+            length_codes: [0; LENS_SIZES],
+            distance_codes: [0; DIST_SIZES],
+            literals: [0; 256],
+        }
+    }
+}
+
 /// Compression header structure
 #[derive(Debug, Clone, Copy)]
 pub struct CompressionHeader {
@@ -172,22 +420,92 @@ pub struct CompressionHeader {
 }
 
 /// Statistics for compression/decompression operations
+///
+/// `ImplodeWriter::stats()` and `ExplodeReader::stats()` report them for the
+/// data processed so far. Fields may be added in future releases, so it can't
+/// be built with a struct literal outside this crate.
 #[derive(Debug, Default, Clone)]
+#[non_exhaustive]
 pub struct CompressionStats {
     /// Number of literal bytes encoded/decoded
     pub literal_count: usize,
     /// Number of distance matches encoded/decoded
     pub match_count: usize,
-    /// Total bytes processed
+    /// Total uncompressed bytes processed
     pub bytes_processed: usize,
     /// Longest match found
     pub longest_match: usize,
-    /// Input bytes (for async operations)
+    /// Bytes read: uncompressed bytes when compressing, compressed bytes
+    /// when decompressing
     pub input_bytes: u64,
-    /// Output bytes (for async operations)
+    /// Bytes produced: compressed bytes when compressing, uncompressed bytes
+    /// when decompressing
     pub output_bytes: u64,
-    /// Compression ratio (for async operations)
+    /// Compressed size divided by uncompressed size
     pub compression_ratio: f64,
+    /// Running CRC32 of the uncompressed data, as computed by `crc32_pklib`
+    pub crc32: u32,
+    /// Token histograms, collected at `StatsLevel::Detailed`
+    pub histograms: Option<Box<TokenHistograms>>,
+}
+
+impl CompressionStats {
+    /// Statistics collecting the given level of detail
+    pub(crate) fn with_level(level: StatsLevel) -> Self {
+        Self {
+            histograms: match level {
+                StatsLevel::Basic => None,
+                StatsLevel::Detailed => Some(Box::default()),
+            },
+            ..Self::default()
+        }
+    }
+
+    /// Level of detail these statistics collect
+    pub(crate) fn level(&self) -> StatsLevel {
+        match self.histograms {
+            Some(_) => StatsLevel::Detailed,
+            None => StatsLevel::Basic,
+        }
+    }
+
+    /// Count a literal byte
+    pub(crate) fn record_literal(&mut self, byte: u8) {
+        self.literal_count += 1;
+        if let Some(histograms) = &mut self.histograms {
+            histograms.literals[byte as usize] += 1;
+        }
+    }
+
+    /// Count a repetition, whose distance is encoded using `dsize_bits`
+    pub(crate) fn record_match(&mut self, length: usize, distance: usize, dsize_bits: u32) {
+        self.match_count += 1;
+        self.longest_match = self.longest_match.max(length);
+
+        if let Some(histograms) = &mut self.histograms {
+            let length_code = LEN_BASE
+                .iter()
+                .rposition(|&base| base as usize <= length - 2)
+                .unwrap_or(0);
+            histograms.length_codes[length_code] += 1;
+
+            // Repetitions of 2 bytes encode the low 2 bits of the distance
+            // after the distance code, longer ones the low `dsize_bits`
+            let low_bits = if length == 2 { 2 } else { dsize_bits };
+            let distance_code = ((distance - 1) >> low_bits).min(DIST_SIZES - 1);
+            histograms.distance_codes[distance_code] += 1;
+        }
+    }
+
+    /// Compressed size divided by uncompressed size, or 0 if there is no
+    /// uncompressed data
+    pub(crate) fn ratio(compressed: u64, uncompressed: u64) -> f64 {
+        if uncompressed > 0 {
+            compressed as f64 / uncompressed as f64
+        } else {
+            0.0
+        }
+    }
 }
 
 #[cfg(test)]
@@ -247,6 +565,28 @@ mod tests {
         assert!(DictionarySize::from_bytes(512).is_err());
     }
 
+    #[test]
+    fn test_detect_mode() {
+        let text = b"The quick brown fox jumps over the lazy dog.\r\n".repeat(20);
+        assert_eq!(CompressionMode::detect(&text), CompressionMode::ASCII);
+
+        let binary: Vec<u8> = (0..=255u8).cycle().take(4096).collect();
+        assert_eq!(CompressionMode::detect(&binary), CompressionMode::Binary);
+        assert_eq!(
+            CompressionMode::detect(&[0xFF; 100]),
+            CompressionMode::Binary
+        );
+
+        assert_eq!(CompressionMode::detect(&[]), CompressionMode::Binary);
+    }
+
+    #[test]
+    fn test_status_codes() {
+        assert_eq!(Status::Ok.code(), 0);
+        assert_eq!(Status::StreamEnd.code(), 1);
+        assert_eq!(Status::GetInput.code(), 11);
+    }
+
     #[test]
     fn test_constants() {
         assert_eq!(MAX_REP_LENGTH, 516);
@@ -1,13 +1,340 @@
 //! Bit decoding and literal/distance decoding functions
 //!
 //! This module implements the core decoding logic for PKLib explode,
-//! including bit manipulation and Huffman-style decoding.
+//! including bit manipulation, Huffman-style decoding and the main
+//! expansion loop.
 
 use super::{state::ExplodeState, *};
-use crate::{CompressionMode, Result};
+use crate::{CompressionMode, PkLibError, Result, StreamPosition, TokenKind};
 use std::io::Read;
 
 impl ExplodeState {
+    /// Read the stream header and set up the decode tables
+    ///
+    /// This loads the first input buffer like PKLib's explode() does, allowing
+    /// the reader to hand out the data in small pieces. Unlike PKLib, which
+    /// wants more than 4 bytes, the 4 byte stream of empty data is accepted.
+    pub fn read_header<R: Read>(&mut self, reader: &mut R) -> Result<()> {
+        self.in_bytes = 0;
+        while self.in_bytes < 4 {
+            let bytes_read = reader.read(&mut self.in_buff[self.in_bytes..])?;
+            if bytes_read == 0 {
+                break;
+            }
+            self.in_bytes += bytes_read;
+            self.in_total += bytes_read as u64;
+        }
+        if self.in_bytes < 4 {
+            return Err(PkLibError::InvalidData("Not enough data".to_string()));
+        }
+
+        let header = [
+            self.in_buff[0],
+            self.in_buff[1],
+            self.in_buff[2],
+            self.in_buff[3],
+        ];
+        self.initialize(&header)
+    }
+
+    /// Decode until a block of output is complete or the stream ends
+    /// Port of the Expand function from PKLib explode.c
+    ///
+    /// Returns `true` once the end of stream marker has been decoded, or the
+    /// input has ended after the last token if no end marker is required.
+    /// Either way, `output_block()` then holds the newly decompressed data.
+    ///
+    /// Fails with `PkLibError::OutputLimitExceeded` right after the token
+    /// that takes the output past `max_output`. `output_tail()` then holds
+    /// the data decoded in this block, up to and including that token.
+    pub fn expand_block<R: Read>(&mut self, reader: &mut R) -> Result<bool> {
+        loop {
+            // The input ended after the previous token
+            if self.input_ended {
+                return Ok(true);
+            }
+
+            let next_literal = if self.require_end_marker {
+                self.expand_token(reader)?
+            } else {
+                self.expand_last_token(reader)?
+            };
+
+            match next_literal {
+                // End of stream
+                LITERAL_END_OF_STREAM => return Ok(true),
+
+                // The input ended in the middle of the stream
+                LITERAL_ERROR => {
+                    return Err(PkLibError::UnexpectedEof.at(self.failure_position()));
+                }
+
+                _ => {}
+            }
+
+            if let Some(limit) = self.max_output {
+                if self.out_total > limit {
+                    return Err(PkLibError::OutputLimitExceeded { limit });
+                }
+            }
+
+            // A block of output is complete
+            if self.output_pos >= 0x2000 {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Decode one token of a stream that may end without an end marker
+    ///
+    /// Like PKLib, decoding a token needs one byte of lookahead past it, so
+    /// the final token fails even when it is complete. It is decoded again
+    /// with zero bytes of padding, and kept if the input holds all of it;
+    /// `input_ended` is then set. Any bits that follow it have to be zero
+    /// padding or complete tokens, otherwise the input ended in the middle of
+    /// a token. A token cut off by the end of the input is `LITERAL_ERROR`.
+    fn expand_last_token<R: Read>(&mut self, reader: &mut R) -> Result<u32> {
+        let mut input = PaddedReader {
+            reader,
+            padded: false,
+        };
+        let (next_literal, minus_dist) = self.decode_token(&mut input)?;
+
+        if !input.padded {
+            self.apply_token(next_literal, minus_dist)?;
+            return Ok(next_literal);
+        }
+
+        // The padding is forgotten once the stream is done, nothing was read
+        // past its end
+        let in_total = self.in_total - 1;
+        self.input_ended = true;
+
+        // The token needed bits past the end of the input
+        if next_literal == LITERAL_ERROR {
+            self.forget_padding(in_total);
+            return Ok(LITERAL_ERROR);
+        }
+
+        self.apply_token(next_literal, minus_dist)?;
+        let result = self.expand_trailing_bits(reader, in_total);
+        self.forget_padding(in_total);
+        result?;
+        Ok(next_literal)
+    }
+
+    /// Decode the tokens held by the bits left after the final token
+    ///
+    /// The bit buffer holds these `extra_bits`, followed by the padding. They
+    /// end the stream once they are all zero, or once they hold an end
+    /// marker. Fails with `UnexpectedEof` if a token needs more of them.
+    fn expand_trailing_bits<R: Read>(&mut self, reader: &mut R, in_total: u64) -> Result<()> {
+        let stream_end = in_total * 8;
+
+        while self.bit_buff & ((1 << self.extra_bits) - 1) != 0 {
+            let token_start = self.bit_position();
+            let token_kind = if self.bit_buff & 1 != 0 {
+                TokenKind::LengthCode
+            } else {
+                TokenKind::Literal
+            };
+
+            let mut input = PaddedReader {
+                reader: &mut *reader,
+                padded: false,
+            };
+            let (next_literal, minus_dist) = self.decode_token(&mut input)?;
+
+            // Only the bits of the input belong to the token. Report the last
+            // part of it that starts within them
+            if next_literal == LITERAL_ERROR || self.bit_position() > stream_end {
+                if self.token_start >= stream_end {
+                    self.token_start = token_start;
+                    self.token_kind = token_kind;
+                }
+                return Err(PkLibError::UnexpectedEof.at(self.failure_position()));
+            }
+            if next_literal == LITERAL_END_OF_STREAM {
+                break;
+            }
+            self.apply_token(next_literal, minus_dist)?;
+        }
+
+        Ok(())
+    }
+
+    /// Drop the padding from the input buffer once the stream has ended
+    fn forget_padding(&mut self, in_total: u64) {
+        self.in_total = in_total;
+        self.in_pos = 0;
+        self.in_bytes = 0;
+    }
+
+    /// Decode one literal or repetition into the output window
+    ///
+    /// Returns the decoded literal value like `decode_lit`. The output window
+    /// is only changed once a token has been decoded completely, so on
+    /// `LITERAL_ERROR` (input ran out) the caller may restore the bit state
+    /// and retry with more input.
+    pub fn expand_token<R: Read>(&mut self, reader: &mut R) -> Result<u32> {
+        let (next_literal, minus_dist) = self.decode_token(reader)?;
+        self.apply_token(next_literal, minus_dist)?;
+        Ok(next_literal)
+    }
+
+    /// Decode the next token without changing the output window
+    ///
+    /// Returns the literal value like `decode_lit`, together with the
+    /// backward distance of a repetition. A repetition whose distance is cut
+    /// off by the end of the input is returned as `LITERAL_ERROR`.
+    fn decode_token<R: Read>(&mut self, reader: &mut R) -> Result<(u32, u32)> {
+        let next_literal = self.decode_lit(reader)?;
+
+        match next_literal {
+            LITERAL_END_OF_STREAM | LITERAL_ERROR => Ok((next_literal, 0)),
+
+            // Repetition (length encoded as literal >= 0x100)
+            literal if literal >= 0x100 => {
+                // Get backward distance to repetition
+                let minus_dist = self.decode_dist(reader, literal - 0xFE)?;
+                if minus_dist == 0 {
+                    return Ok((LITERAL_ERROR, 0));
+                }
+                Ok((literal, minus_dist))
+            }
+
+            // Literal byte (< 0x100)
+            literal => Ok((literal, 0)),
+        }
+    }
+
+    /// Write a decoded token to the output window
+    fn apply_token(&mut self, next_literal: u32, minus_dist: u32) -> Result<()> {
+        match next_literal {
+            LITERAL_END_OF_STREAM | LITERAL_ERROR => {}
+
+            // Repetition (length encoded as literal >= 0x100)
+            literal if literal >= 0x100 => {
+                // Calculate repetition length
+                let rep_length = literal - 0xFE;
+
+                // The repetition has to start within the dictionary or the
+                // data decompressed so far
+                if self.strict_distances
+                    && minus_dist as u64 > self.out_total + self.dict_len as u64
+                {
+                    return Err(PkLibError::InvalidDistance(minus_dist).at(self.failure_position()));
+                }
+
+                // Calculate source and target positions
+                let target_pos = self.output_pos;
+                let source_pos = target_pos.saturating_sub(minus_dist as usize);
+
+                // Bounds checking
+                if source_pos >= self.out_buff.len()
+                    || target_pos + rep_length as usize > self.out_buff.len()
+                {
+                    return Err(
+                        PkLibError::DecompressionError("Buffer overflow".to_string())
+                            .at(self.failure_position()),
+                    );
+                }
+
+                // Copy the repeating sequence (may overlap)
+                for i in 0..rep_length as usize {
+                    self.out_buff[target_pos + i] = self.out_buff[source_pos + i];
+                }
+
+                self.output_pos += rep_length as usize;
+                self.out_total += rep_length as u64;
+                self.stats
+                    .record_match(rep_length as usize, minus_dist as usize, self.dsize_bits);
+            }
+
+            // Literal byte (< 0x100)
+            literal => {
+                if self.output_pos < self.out_buff.len() {
+                    self.out_buff[self.output_pos] = literal as u8;
+                    self.output_pos += 1;
+                    self.out_total += 1;
+                    self.stats.record_literal(literal as u8);
+                } else {
+                    return Err(PkLibError::DecompressionError(
+                        "Output buffer overflow".to_string(),
+                    )
+                    .at(self.failure_position()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decompressed data of the current block
+    pub fn output_block(&self) -> &[u8] {
+        &self.out_buff[0x1000..self.output_pos.min(0x2000)]
+    }
+
+    /// Decompressed data of the current block, including any bytes the last
+    /// repetition wrote past the end of the block
+    ///
+    /// For handing out the data once decoding has stopped for good.
+    pub fn output_tail(&self) -> &[u8] {
+        &self.out_buff[0x1000..self.output_pos]
+    }
+
+    /// Input that was read past the end of the stream
+    ///
+    /// Once the end of the stream has been decoded, these are the bytes read
+    /// into the input buffer that don't belong to it: the byte held in the
+    /// bit buffer as lookahead, followed by the rest of the buffer. If the
+    /// input ran out while decoding the end, nothing was read past it.
+    pub fn unread_input(&self) -> &[u8] {
+        if self.in_bytes == 0 {
+            return &[];
+        }
+        &self.in_buff[self.in_pos.saturating_sub(1)..self.in_bytes]
+    }
+
+    /// Number of input bytes the stream occupied, once its end was decoded
+    pub fn consumed_input(&self) -> u64 {
+        self.in_total - self.unread_input().len() as u64
+    }
+
+    /// Number of bits of the stream consumed so far, including the header
+    pub fn bit_position(&self) -> u64 {
+        // The bit buffer holds 8 + extra_bits unread bits of the loaded bytes
+        let loaded = self.in_total - (self.in_bytes - self.in_pos) as u64;
+        loaded * 8 - 8 - self.extra_bits as u64
+    }
+
+    /// Record that decoding of the given token part starts here
+    fn begin_token(&mut self, kind: TokenKind) {
+        self.token_kind = kind;
+        self.token_start = self.bit_position();
+    }
+
+    /// Position of the token part that is being decoded, for error reports
+    pub fn failure_position(&self) -> StreamPosition {
+        StreamPosition {
+            byte_offset: self.token_start / 8,
+            bit_offset: (self.token_start % 8) as u8,
+            output_offset: self.out_total,
+            token: self.token_kind,
+        }
+    }
+
+    /// Move past a complete output block
+    ///
+    /// The flushed data moves to the first half of the buffer, where it serves
+    /// as the dictionary for repetitions. Bytes beyond 0x2000 produced by a
+    /// repetition that crossed the boundary move along with it and belong to
+    /// the next block.
+    pub fn next_block(&mut self) {
+        self.out_buff.copy_within(0x1000..self.output_pos, 0);
+        self.output_pos -= 0x1000;
+    }
+
     /// Remove given number of bits from bit buffer, loading new data if needed
     /// Port of WasteBits function from PKLib explode.c
     pub fn waste_bits<R: Read>(&mut self, reader: &mut R, n_bits: u32) -> Result<u32> {
@@ -28,6 +355,7 @@ impl ExplodeState {
             if self.in_bytes == 0 {
                 return Ok(PKDCL_STREAM_END);
             }
+            self.in_total += self.in_bytes as u64;
         }
 
         // Update bit buffer with new byte
@@ -52,8 +380,12 @@ impl ExplodeState {
     /// - 0x305: End of stream
     /// - 0x306: Error
     pub fn decode_lit<R: Read>(&mut self, reader: &mut R) -> Result<u32> {
+        self.begin_token(TokenKind::Literal);
+
         // Test the current bit in buffer
         if (self.bit_buff & 1) != 0 {
+            self.token_kind = TokenKind::LengthCode;
+
             // Remove one bit from input data
             if self.waste_bits(reader, 1)? != PKDCL_OK {
                 return Ok(LITERAL_ERROR);
@@ -72,6 +404,7 @@ impl ExplodeState {
             let mut final_length_code = length_code;
 
             if extra_length_bits != 0 {
+                self.begin_token(TokenKind::ExtraBits);
                 let extra_length = self.bit_buff & ((1 << extra_length_bits) - 1);
 
                 if self.waste_bits(reader, extra_length_bits as u32)? != PKDCL_OK
@@ -136,6 +469,8 @@ impl ExplodeState {
     /// Decode distance for repetition
     /// Port of DecodeDist function from PKLib explode.c
     pub fn decode_dist<R: Read>(&mut self, reader: &mut R, rep_length: u32) -> Result<u32> {
+        self.begin_token(TokenKind::Distance);
+
         // Get distance position code from next 2-8 bits
         let dist_pos_code = self.dist_pos_codes[(self.bit_buff & 0xFF) as usize];
         let dist_pos_bits = self.dist_bits[dist_pos_code as usize];
@@ -164,3 +499,23 @@ impl ExplodeState {
         Ok(distance + 1)
     }
 }
+
+/// Reader that yields one zero byte of padding once the input has ended
+struct PaddedReader<'a, R: Read> {
+    reader: &'a mut R,
+    /// Whether the padding byte was handed out
+    padded: bool,
+}
+
+impl<R: Read> Read for PaddedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self.reader.read(buf)?;
+        if bytes_read > 0 || self.padded || buf.is_empty() {
+            return Ok(bytes_read);
+        }
+
+        buf[0] = 0;
+        self.padded = true;
+        Ok(1)
+    }
+}
@@ -4,13 +4,22 @@
 //! It implements the explode algorithm exactly as specified in the original PKLib.
 
 mod decoder;
+mod multi;
+mod push;
 mod reader;
 mod state;
+mod tokens;
 
+pub use multi::{
+    explode_multi, explode_multi_limited, explode_multi_with_members, ExplodeMember,
+    MultiExplodeReader,
+};
+pub use push::ExplodeDecoder;
 pub use reader::ExplodeReader;
 pub use state::ExplodeState;
+pub use tokens::{Token, TokenInfo, TokenReader};
 
-use crate::Result;
+use crate::{PkLibError, Result};
 use std::io::Read;
 
 /// Input buffer size for decompression (2048 bytes)
@@ -58,14 +67,192 @@ pub const LITERAL_END_OF_STREAM: u32 = 0x305;
 /// Literal decoding error marker (0x306)
 pub const LITERAL_ERROR: u32 = 0x306;
 
+/// Options controlling how compressed streams are decoded
+#[derive(Debug, Clone)]
+pub struct ExplodeOptions {
+    /// Require the stream to end with an end of stream marker (0x305), like
+    /// PKLib does
+    ///
+    /// When disabled, the end of the input is accepted as the end of the
+    /// stream, for producers that omit the end marker. The stream has to end
+    /// on a token boundary, optionally followed by zero bits up to the end of
+    /// the byte, and every complete token is kept. Input that ends in the
+    /// middle of a token, including nonzero bits after the last complete
+    /// one, still fails with `PkLibError::UnexpectedEof` at its position.
+    pub require_end_marker: bool,
+    /// Maximum number of decompressed bytes
+    ///
+    /// Decompression fails with `PkLibError::OutputLimitExceeded` before the
+    /// output grows past this limit. As a single repetition of up to 516 bytes
+    /// takes only a few bits, a small input can expand enormously, so set a
+    /// limit when decompressing untrusted data.
+    pub max_output: Option<u64>,
+    /// Reject repetitions that reach before the first decompressed byte (or
+    /// the start of the preset dictionary) with `PkLibError::InvalidDistance`,
+    /// reported as the `root_cause()` of a `PkLibError::DecodeFailed`
+    ///
+    /// PKLib doesn't check this and copies whatever the output window held
+    /// before, so corrupt data turns into garbage instead of an error.
+    /// Disable only to decode such streams the way PKLib does.
+    pub strict_distances: bool,
+    /// Keep the data decoded before a fatal error
+    ///
+    /// Instead of failing right away, the reader first hands out every byte
+    /// decoded before the error, then reports it. This salvages the intact
+    /// part of damaged data.
+    pub recover: bool,
+}
+
+impl Default for ExplodeOptions {
+    fn default() -> Self {
+        Self {
+            require_end_marker: true,
+            max_output: None,
+            strict_distances: true,
+            recover: false,
+        }
+    }
+}
+
 /// Convenience function to decompress data in memory
 pub fn explode_bytes(data: &[u8]) -> Result<Vec<u8>> {
-    let mut reader = ExplodeReader::new(std::io::Cursor::new(data))?;
+    explode_bytes_with_options(data, ExplodeOptions::default())
+}
+
+/// Convenience function to decompress data in memory using the given options
+pub fn explode_bytes_with_options(data: &[u8], options: ExplodeOptions) -> Result<Vec<u8>> {
+    let mut reader = ExplodeReader::with_options(std::io::Cursor::new(data), options)?;
     let mut output = Vec::new();
-    reader.read_to_end(&mut output)?;
+    reader.read_to_end(&mut output).map_err(unwrap_io_error)?;
+    Ok(output)
+}
+
+/// Convenience function to decompress untrusted data in memory
+///
+/// Fails with `PkLibError::OutputLimitExceeded` if the decompressed data would
+/// be larger than `max_output` bytes.
+pub fn explode_bytes_limited(data: &[u8], max_output: u64) -> Result<Vec<u8>> {
+    let options = ExplodeOptions {
+        max_output: Some(max_output),
+        ..ExplodeOptions::default()
+    };
+    explode_bytes_with_options(data, options)
+}
+
+/// Most bytes one byte of compressed data can decompress to
+///
+/// The cheapest repetition of the longest length (516 bytes) takes 22 bits.
+const MAX_EXPANSION: usize = 188;
+
+/// Convenience function to decompress data of a known length in memory
+///
+/// For formats that store the uncompressed size next to the compressed data.
+/// Decoding stops at the first token that takes the output past
+/// `expected_len`. Fails with `PkLibError::LengthMismatch` if the stream ends
+/// before or runs past `expected_len` bytes.
+///
+/// The output is allocated once, unless `expected_len` is more than `data`
+/// can possibly decompress to: as the length usually comes from untrusted
+/// metadata, the allocation is capped at that size.
+pub fn explode_bytes_exact(data: &[u8], expected_len: usize) -> Result<Vec<u8>> {
+    let mut input = data;
+    let mut state = ExplodeState::new();
+    state.max_output = Some(expected_len as u64);
+    state.read_header(&mut input)?;
+
+    let mut output = Vec::with_capacity(expected_len.min(data.len().saturating_mul(MAX_EXPANSION)));
+    loop {
+        let finished = match state.expand_block(&mut input) {
+            Ok(finished) => finished,
+            Err(PkLibError::OutputLimitExceeded { .. }) => {
+                return Err(PkLibError::LengthMismatch {
+                    expected: expected_len,
+                    actual: state.out_total as usize,
+                });
+            }
+            Err(e) => return Err(e),
+        };
+
+        output.extend_from_slice(state.output_block());
+        if finished {
+            break;
+        }
+        state.next_block();
+    }
+
+    if output.len() < expected_len {
+        return Err(PkLibError::LengthMismatch {
+            expected: expected_len,
+            actual: output.len(),
+        });
+    }
     Ok(output)
 }
 
+/// Convenience function to decompress as much of damaged data as possible
+///
+/// Returns everything decoded before the first fatal error, together with
+/// that error. `PkLibError::position` tells where it occurred.
+pub fn explode_bytes_recover(data: &[u8]) -> (Vec<u8>, Option<PkLibError>) {
+    let options = ExplodeOptions {
+        recover: true,
+        ..ExplodeOptions::default()
+    };
+    let mut output = Vec::new();
+    let error = ExplodeReader::with_options(data, options)
+        .and_then(|mut reader| reader.read_to_end(&mut output).map_err(unwrap_io_error))
+        .err();
+    (output, error)
+}
+
+/// Convenience function to decompress a stream embedded in larger data
+///
+/// Returns the decompressed data together with the number of bytes of `data`
+/// the compressed stream occupied, so parsing can continue right after it.
+pub fn explode_bytes_with_consumed(data: &[u8]) -> Result<(Vec<u8>, usize)> {
+    let mut reader = ExplodeReader::new(data)?;
+    let mut output = Vec::new();
+    reader.read_to_end(&mut output).map_err(unwrap_io_error)?;
+    Ok((output, reader.consumed() as usize))
+}
+
+/// Recover the error that a reader wrapped in an `io::Error`
+///
+/// The readers report decompression errors through `Read`, so in-memory
+/// helpers unwrap them again to return the original `PkLibError`.
+pub(crate) fn unwrap_io_error(error: std::io::Error) -> PkLibError {
+    error
+        .downcast::<PkLibError>()
+        .unwrap_or_else(PkLibError::Io)
+}
+
+/// Decompress data from one slice into another without allocating
+///
+/// Returns the number of bytes written to `dst`, or
+/// `PkLibError::BufferTooSmall` if the decompressed data doesn't fit.
+pub fn explode_into(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    let mut input = src;
+    let mut state = ExplodeState::new();
+    state.read_header(&mut input)?;
+
+    let mut written = 0;
+    loop {
+        let finished = state.expand_block(&mut input)?;
+
+        let block = state.output_block();
+        let end = written + block.len();
+        dst.get_mut(written..end)
+            .ok_or(PkLibError::BufferTooSmall)?
+            .copy_from_slice(block);
+        written = end;
+
+        if finished {
+            return Ok(written);
+        }
+        state.next_block();
+    }
+}
+
 /// Decompress MPQ-format PKWare data
 /// MPQ format has different header structure than standard PKLib
 pub fn explode_mpq_bytes(data: &[u8]) -> Result<Vec<u8>> {
@@ -0,0 +1,235 @@
+//! MultiExplodeReader - Decompression of concatenated streams
+//!
+//! Some archives store several compressed members back to back. This module
+//! decodes them one after another: once a member's end marker is reached, the
+//! next member's header starts at the following byte, and decoding continues
+//! until the input ends.
+
+use super::reader::ExplodeReader;
+use crate::{CompressionMode, DictionarySize, PkLibError, Result};
+use std::io::Read;
+
+/// Position, size and settings of one member of a concatenated stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExplodeMember {
+    /// Offset of the member in the compressed input
+    pub compressed_offset: u64,
+    /// Size of the member in the compressed input
+    pub compressed_len: u64,
+    /// Offset of the member's data in the decompressed output
+    pub decompressed_offset: u64,
+    /// Size of the member's decompressed data
+    pub decompressed_len: u64,
+    /// Compression mode of the member
+    pub mode: CompressionMode,
+    /// Dictionary size of the member
+    pub dict_size: DictionarySize,
+}
+
+/// Input of a member: the bytes read past the end of the previous member,
+/// followed by the rest of the underlying reader
+#[derive(Debug)]
+struct MemberInput<R: Read> {
+    remainder: Vec<u8>,
+    pos: usize,
+    reader: R,
+}
+
+impl<R: Read> MemberInput<R> {
+    /// Check whether the input has ended, reading ahead if necessary
+    fn at_eof(&mut self) -> std::io::Result<bool> {
+        if self.pos < self.remainder.len() {
+            return Ok(false);
+        }
+
+        let mut byte = [0u8; 1];
+        if self.reader.read(&mut byte)? == 0 {
+            return Ok(true);
+        }
+        self.remainder = byte.to_vec();
+        self.pos = 0;
+        Ok(false)
+    }
+}
+
+impl<R: Read> Read for MemberInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos < self.remainder.len() {
+            let count = buf.len().min(self.remainder.len() - self.pos);
+            buf[..count].copy_from_slice(&self.remainder[self.pos..self.pos + count]);
+            self.pos += count;
+            return Ok(count);
+        }
+        self.reader.read(buf)
+    }
+}
+
+/// Where the reader is within the concatenated stream
+#[derive(Debug)]
+enum Member<R: Read> {
+    /// Before the header of the next member, if there is one
+    Between(MemberInput<R>),
+    /// Decoding a member
+    Inside(Box<ExplodeReader<MemberInput<R>>>),
+    /// The input has ended
+    Done,
+}
+
+/// Streaming decompression reader for concatenated compressed streams
+///
+/// Reads the decompressed data of all members in order, as one stream. Each
+/// member may use a different compression mode and dictionary size; the
+/// members decoded so far are listed by `members()`.
+#[derive(Debug)]
+pub struct MultiExplodeReader<R: Read> {
+    member: Member<R>,
+    members: Vec<ExplodeMember>,
+    compressed_offset: u64,
+    decompressed_offset: u64,
+    decompressed_len: u64,
+    max_output: Option<u64>,
+}
+
+impl<R: Read> MultiExplodeReader<R> {
+    /// Create a new MultiExplodeReader
+    pub fn new(reader: R) -> Result<Self> {
+        Ok(Self {
+            member: Member::Between(MemberInput {
+                remainder: Vec::new(),
+                pos: 0,
+                reader,
+            }),
+            members: Vec::new(),
+            compressed_offset: 0,
+            decompressed_offset: 0,
+            decompressed_len: 0,
+            max_output: None,
+        })
+    }
+
+    /// Limit the decompressed data of all members together to `max_output`
+    /// bytes
+    ///
+    /// Reading hands out the first `max_output` bytes, then fails with
+    /// `PkLibError::OutputLimitExceeded` (wrapped in an `io::Error`) if the
+    /// members hold more.
+    pub fn with_limit(mut self, max_output: u64) -> Self {
+        self.max_output = Some(max_output);
+        self
+    }
+
+    /// The members decoded completely so far
+    pub fn members(&self) -> &[ExplodeMember] {
+        &self.members
+    }
+
+    /// Report a member running out of its share of the limit as the limit of
+    /// all members being exceeded
+    fn limit_error(&self, error: std::io::Error) -> std::io::Error {
+        let exceeded = matches!(
+            error.get_ref().and_then(|e| e.downcast_ref::<PkLibError>()),
+            Some(PkLibError::OutputLimitExceeded { .. })
+        );
+        match self.max_output {
+            Some(limit) if exceeded => std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                PkLibError::OutputLimitExceeded { limit },
+            ),
+            _ => error,
+        }
+    }
+
+    /// Record the member that just ended and move on to the input after it
+    fn finish_member(&mut self, reader: ExplodeReader<MemberInput<R>>) -> Result<()> {
+        let header = reader.header().ok_or(PkLibError::InvalidFormat)?;
+        let compressed_len = reader.consumed();
+        let (mut input, mut remainder) = reader.into_inner_with_remainder();
+
+        // Input read ahead past the end of the member starts the next one
+        remainder.extend_from_slice(&input.remainder[input.pos..]);
+        input.remainder = remainder;
+        input.pos = 0;
+
+        self.members.push(ExplodeMember {
+            compressed_offset: self.compressed_offset,
+            compressed_len,
+            decompressed_offset: self.decompressed_offset,
+            decompressed_len: self.decompressed_len,
+            mode: header.mode,
+            dict_size: header.dict_size,
+        });
+        self.compressed_offset += compressed_len;
+        self.decompressed_offset += self.decompressed_len;
+        self.decompressed_len = 0;
+
+        self.member = Member::Between(input);
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for MultiExplodeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            match std::mem::replace(&mut self.member, Member::Done) {
+                Member::Between(mut input) => {
+                    if !input.at_eof()? {
+                        let mut reader = ExplodeReader::new(input)
+                            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                        // The member may use what the previous ones left
+                        if let Some(limit) = self.max_output {
+                            reader = reader.with_limit(limit - self.decompressed_offset);
+                        }
+                        self.member = Member::Inside(Box::new(reader));
+                    }
+                }
+                Member::Inside(mut reader) => {
+                    let count = reader.read(buf).map_err(|e| self.limit_error(e))?;
+                    if count > 0 {
+                        self.decompressed_len += count as u64;
+                        self.member = Member::Inside(reader);
+                        return Ok(count);
+                    }
+                    self.finish_member(*reader)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                }
+                Member::Done => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Convenience function to decompress concatenated streams in memory
+///
+/// Returns the decompressed data of all members, one after another.
+pub fn explode_multi(data: &[u8]) -> Result<Vec<u8>> {
+    Ok(explode_multi_with_members(data)?.0)
+}
+
+/// Convenience function to decompress concatenated streams of untrusted data
+/// in memory
+///
+/// Fails with `PkLibError::OutputLimitExceeded` if the members together would
+/// decompress to more than `max_output` bytes.
+pub fn explode_multi_limited(data: &[u8], max_output: u64) -> Result<Vec<u8>> {
+    let mut reader = MultiExplodeReader::new(data)?.with_limit(max_output);
+    let mut output = Vec::new();
+    reader
+        .read_to_end(&mut output)
+        .map_err(super::unwrap_io_error)?;
+    Ok(output)
+}
+
+/// Convenience function to decompress concatenated streams in memory,
+/// also returning where each member starts and ends
+pub fn explode_multi_with_members(data: &[u8]) -> Result<(Vec<u8>, Vec<ExplodeMember>)> {
+    let mut reader = MultiExplodeReader::new(data)?;
+    let mut output = Vec::new();
+    reader
+        .read_to_end(&mut output)
+        .map_err(super::unwrap_io_error)?;
+    Ok((output, reader.members))
+}
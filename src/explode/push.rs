@@ -0,0 +1,278 @@
+//! ExplodeDecoder - Push-style (sans-IO) decompression
+//!
+//! This module implements a decompressor that is fed compressed data by the
+//! caller instead of pulling it from a `Read`, for data that arrives in
+//! arbitrary fragments such as network frames. It drives the same decoding
+//! code as `ExplodeReader`, buffering an incomplete token until the rest of
+//! it arrives.
+
+use super::{state::ExplodeState, LITERAL_END_OF_STREAM, LITERAL_ERROR};
+use crate::{crc32_pklib, CompressionStats, PkLibError, Result, Status};
+
+/// Bit reader position, saved before each token so it can be decoded again
+/// once more input is available
+type BitState = (u32, u32, usize, usize);
+
+/// Outcome of decoding the buffered input
+enum Progress {
+    /// The current output block is complete
+    BlockFull,
+    /// The next token needs more input
+    NeedInput,
+    /// The end of stream marker was decoded
+    End,
+}
+
+/// Push-style decompressor for callers that receive compressed data in pieces
+///
+/// Feed compressed data to `decompress()` in fragments of any size, splitting
+/// at any byte. Each call consumes as much input and fills as much output as
+/// it can, and reports how to continue:
+///
+/// - `Status::Ok`: the output buffer is full, call again with more space
+/// - `Status::GetInput`: all input was consumed, call again with more input
+/// - `Status::StreamEnd`: the stream is complete and all output delivered
+///
+/// Once the end of the stream is reached, any input after it is left
+/// unconsumed.
+#[derive(Debug)]
+pub struct ExplodeDecoder {
+    state: ExplodeState,
+    initialized: bool,
+    finished: bool,
+    /// Position in the output window up to which data was handed out
+    delivered: usize,
+    /// Number of compressed bytes consumed
+    total_in: u64,
+    /// Number of decompressed bytes handed out
+    total_out: u64,
+    /// Running CRC32 of the decompressed data handed out
+    crc32: u32,
+}
+
+impl ExplodeDecoder {
+    /// Create a new ExplodeDecoder
+    pub fn new() -> Self {
+        let state = ExplodeState::new();
+        let delivered = state.output_pos;
+        Self {
+            state,
+            initialized: false,
+            finished: false,
+            delivered,
+            total_in: 0,
+            total_out: 0,
+            crc32: 0,
+        }
+    }
+
+    /// Use a preset dictionary for decompression
+    ///
+    /// The dictionary must be the one the data was compressed with, and has to
+    /// be set before the first call to `decompress()`.
+    pub fn with_dictionary(mut self, dictionary: &[u8]) -> Result<Self> {
+        if self.initialized || self.state.in_bytes != 0 {
+            return Err(PkLibError::InvalidData(
+                "Dictionary must be set before decompressing".to_string(),
+            ));
+        }
+
+        self.state.set_dictionary(dictionary);
+        Ok(self)
+    }
+
+    /// Whether the end of the compressed stream has been reached
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Statistics of the data decompressed so far
+    ///
+    /// `input_bytes` counts the compressed bytes consumed, and
+    /// `output_bytes` the decompressed bytes handed out.
+    pub fn stats(&self) -> CompressionStats {
+        let mut stats = self.state.stats.clone();
+        stats.input_bytes = self.total_in;
+        stats.output_bytes = self.total_out;
+        stats.bytes_processed = self.total_out as usize;
+        stats.compression_ratio = CompressionStats::ratio(stats.input_bytes, stats.output_bytes);
+        stats.crc32 = self.crc32;
+        stats
+    }
+
+    /// Decompress from `input` into `output`
+    ///
+    /// Returns the number of input bytes consumed, the number of output bytes
+    /// produced, and the status telling how to continue. Unconsumed input has
+    /// to be passed again on the next call.
+    pub fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(usize, usize, Status)> {
+        let mut consumed = 0;
+        let mut produced = 0;
+        let mut need_input = false;
+
+        let status = loop {
+            produced += self.drain(&mut output[produced..]);
+            if self.delivered < self.block_end() {
+                break Status::Ok;
+            }
+            if self.finished {
+                break Status::StreamEnd;
+            }
+
+            // The whole block was handed out, keep it only as the dictionary
+            if self.state.output_pos >= 0x2000 {
+                self.state.next_block();
+                self.delivered -= 0x1000;
+            }
+
+            if need_input {
+                break Status::GetInput;
+            }
+
+            consumed += self.fill(&input[consumed..]);
+            match self.decode()? {
+                Progress::BlockFull => {}
+                Progress::NeedInput => need_input = consumed == input.len(),
+                Progress::End => {
+                    self.finished = true;
+                    consumed -= self.unused_input().min(consumed);
+                    self.state.in_pos = self.state.in_bytes;
+                }
+            }
+        };
+
+        // Hand back input that was buffered but not decoded yet, so that
+        // nothing beyond the end of the stream is consumed
+        if status == Status::Ok {
+            let returned = (self.state.in_bytes - self.state.in_pos).min(consumed);
+            self.state.in_bytes -= returned;
+            self.state.in_total -= returned as u64;
+            consumed -= returned;
+        }
+
+        self.total_in += consumed as u64;
+        self.total_out += produced as u64;
+        Ok((consumed, produced, status))
+    }
+
+    /// End of the decompressed data of the current block
+    ///
+    /// Once the stream has ended, this includes the bytes the last repetition
+    /// wrote past the end of the block.
+    fn block_end(&self) -> usize {
+        if self.finished {
+            return self.state.output_pos;
+        }
+        self.state.output_pos.min(0x2000)
+    }
+
+    /// Copy decompressed data that was not handed out yet into `output`
+    fn drain(&mut self, output: &mut [u8]) -> usize {
+        let count = (self.block_end() - self.delivered).min(output.len());
+        let data = &self.state.out_buff[self.delivered..self.delivered + count];
+        output[..count].copy_from_slice(data);
+        self.crc32 = crc32_pklib(data, self.crc32);
+        self.delivered += count;
+        count
+    }
+
+    /// Append input to the input buffer, returning the number of bytes taken
+    fn fill(&mut self, input: &[u8]) -> usize {
+        let state = &mut self.state;
+        state.in_buff.copy_within(state.in_pos..state.in_bytes, 0);
+        state.in_bytes -= state.in_pos;
+        state.in_pos = 0;
+
+        let count = (state.in_buff.len() - state.in_bytes).min(input.len());
+        state.in_buff[state.in_bytes..state.in_bytes + count].copy_from_slice(&input[..count]);
+        state.in_bytes += count;
+        state.in_total += count as u64;
+        count
+    }
+
+    /// Decode buffered input until the block is full, the input runs out or
+    /// the stream ends
+    fn decode(&mut self) -> Result<Progress> {
+        if !self.initialized {
+            if self.state.in_bytes < 4 {
+                return Ok(Progress::NeedInput);
+            }
+            let header = [
+                self.state.in_buff[0],
+                self.state.in_buff[1],
+                self.state.in_buff[2],
+                self.state.in_buff[3],
+            ];
+            self.state.initialize(&header)?;
+            self.initialized = true;
+        }
+
+        // Input is only taken from the buffer: reaching its end makes the
+        // current token fail, and it is decoded again when more input arrives
+        let mut no_input = std::io::empty();
+
+        loop {
+            let saved = self.save();
+
+            // The end of stream marker needs no room in the output window,
+            // so it is recognized even when the block is complete
+            if self.state.output_pos >= 0x2000 {
+                if self.state.decode_lit(&mut no_input)? == LITERAL_END_OF_STREAM {
+                    return Ok(Progress::End);
+                }
+                self.restore(saved);
+                return Ok(Progress::BlockFull);
+            }
+
+            match self.state.expand_token(&mut no_input)? {
+                LITERAL_END_OF_STREAM => return Ok(Progress::End),
+                LITERAL_ERROR => {
+                    self.restore(saved);
+                    return Ok(Progress::NeedInput);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Number of buffered input bytes after the end of the stream
+    fn unused_input(&self) -> usize {
+        // The end marker was completed from the last bits of the input
+        if self.state.in_bytes == 0 {
+            return 0;
+        }
+
+        // Otherwise the bit buffer already holds the byte following the stream
+        self.state.in_bytes - self.state.in_pos + 1
+    }
+
+    fn save(&self) -> BitState {
+        let state = &self.state;
+        (
+            state.bit_buff,
+            state.extra_bits,
+            state.in_pos,
+            state.in_bytes,
+        )
+    }
+
+    fn restore(&mut self, saved: BitState) {
+        let state = &mut self.state;
+        (
+            state.bit_buff,
+            state.extra_bits,
+            state.in_pos,
+            state.in_bytes,
+        ) = saved;
+    }
+}
+
+impl Default for ExplodeDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -3,11 +3,8 @@
 //! This module implements the ExplodeReader that provides a Read interface
 //! for PKLib explode decompression, including the main expansion logic.
 
-use super::{state::ExplodeState, *};
-use crate::tables::{
-    CH_BITS_ASC, CH_CODE_ASC, DIST_BITS, DIST_CODE, EX_LEN_BITS, LEN_BASE, LEN_BITS, LEN_CODE,
-};
-use crate::{CompressionMode, PkLibError, Result};
+use super::{state::ExplodeState, ExplodeOptions};
+use crate::{crc32_pklib, CompressionHeader, CompressionStats, PkLibError, Result, StatsLevel};
 use std::io::Read;
 
 /// Streaming decompression reader implementing Read trait
@@ -19,161 +16,147 @@ pub struct ExplodeReader<R: Read> {
     finished: bool,
     output_buffer: Vec<u8>,
     output_pos: usize,
+    total_out: u64,
+    recover: bool,
+    /// Error that stopped decoding, reported by every read once the data
+    /// before it was read
+    error: Option<PkLibError>,
+    /// Running CRC32 of the decompressed data
+    crc32: u32,
 }
 
 impl<R: Read> ExplodeReader<R> {
     /// Create a new ExplodeReader
     pub fn new(reader: R) -> Result<Self> {
+        Self::with_options(reader, ExplodeOptions::default())
+    }
+
+    /// Create a new ExplodeReader using the given options
+    pub fn with_options(reader: R, options: ExplodeOptions) -> Result<Self> {
+        let mut state = ExplodeState::new();
+        state.require_end_marker = options.require_end_marker;
+        state.strict_distances = options.strict_distances;
+        state.max_output = options.max_output;
         Ok(Self {
             reader,
-            state: ExplodeState::new(),
+            state,
             initialized: false,
             finished: false,
             output_buffer: Vec::new(),
             output_pos: 0,
+            total_out: 0,
+            recover: options.recover,
+            error: None,
+            crc32: 0,
         })
     }
 
-    /// Initialize the reader by reading and parsing the header
-    fn initialize(&mut self) -> Result<()> {
-        if self.initialized {
-            return Ok(());
-        }
-
-        // Load initial input buffer (similar to PKLib read_buf call)
-        self.state.in_bytes = self.reader.read(&mut self.state.in_buff)?;
-        if self.state.in_bytes <= 4 {
-            return Err(PkLibError::InvalidData("Not enough data".to_string()));
-        }
-
-        // Extract header from buffer (like PKLib does)
-        self.state.ctype = match self.state.in_buff[0] {
-            0 => CompressionMode::Binary,
-            1 => CompressionMode::ASCII,
-            _ => return Err(PkLibError::InvalidCompressionMode(self.state.in_buff[0])),
-        };
-
-        self.state.dsize_bits = self.state.in_buff[1] as u32;
-        self.state.bit_buff = self.state.in_buff[2] as u32;
-        self.state.extra_bits = 0;
-        self.state.in_pos = 3; // Skip header bytes
-
-        // Debug log for MPQ files
-        if self.state.in_buff[0] == 0 && self.state.in_buff[1] == 6 {
-            eprintln!(
-                "PKLib MPQ header detected: ctype={}, dsize_bits={}, bit_buff=0x{:02X}",
-                self.state.in_buff[0], self.state.dsize_bits, self.state.bit_buff
-            );
-        }
+    /// Limit the decompressed data to `max_output` bytes
+    ///
+    /// Reading hands out the first `max_output` bytes, then fails with
+    /// `PkLibError::OutputLimitExceeded` (wrapped in an `io::Error`) if the
+    /// stream holds more. Decoding stops at the token that passes the limit.
+    pub fn with_limit(mut self, max_output: u64) -> Self {
+        self.state.max_output = Some(max_output);
+        self
+    }
 
-        // Validate dictionary size
-        if self.state.dsize_bits < 4 || self.state.dsize_bits > 6 {
-            return Err(PkLibError::InvalidDictionaryBits(
-                self.state.dsize_bits as u8,
+    /// Use a preset dictionary for decompression
+    ///
+    /// The dictionary must be the one the data was compressed with (see
+    /// `ImplodeWriter::with_dictionary`), and has to be set before reading.
+    pub fn with_dictionary(mut self, dictionary: &[u8]) -> Result<Self> {
+        if self.initialized {
+            return Err(PkLibError::InvalidData(
+                "Dictionary must be set before reading".to_string(),
             ));
         }
 
-        self.state.dsize_mask = 0xFFFF >> (16 - self.state.dsize_bits);
-
-        // Copy static tables
-        self.state.dist_bits.copy_from_slice(&DIST_BITS);
-        self.state.len_bits.copy_from_slice(&LEN_BITS);
-        self.state.ex_len_bits.copy_from_slice(&EX_LEN_BITS);
-        self.state.len_base.copy_from_slice(&LEN_BASE);
-
-        // Generate decode tables
-        Self::gen_decode_tabs(&mut self.state.length_codes, &LEN_CODE, &LEN_BITS);
-        Self::gen_decode_tabs(&mut self.state.dist_pos_codes, &DIST_CODE, &DIST_BITS);
-
-        // Generate ASCII tables if needed
-        if matches!(self.state.ctype, CompressionMode::ASCII) {
-            self.state.ch_bits_asc.copy_from_slice(&CH_BITS_ASC);
-            self.gen_asc_tabs();
-        }
-
-        self.initialized = true;
-        Ok(())
+        self.state.set_dictionary(dictionary);
+        Ok(self)
     }
 
-    /// Generate decode tables (port of GenDecodeTabs from PKLib)
-    fn gen_decode_tabs(positions: &mut [u8], start_indexes: &[u8], length_bits: &[u8]) {
-        for i in 0..start_indexes.len() {
-            let length = 1u32 << length_bits[i];
-            let mut index = start_indexes[i] as u32;
-
-            while index < 0x100 {
-                if (index as usize) < positions.len() {
-                    positions[index as usize] = i as u8;
-                }
-                index += length;
-            }
+    /// Compression mode and dictionary size of the stream, once its header
+    /// has been read
+    pub fn header(&self) -> Option<CompressionHeader> {
+        if !self.initialized {
+            return None;
         }
+        self.state.header()
     }
 
-    /// Generate ASCII decode tables (port of GenAscTabs from PKLib)
-    fn gen_asc_tabs(&mut self) {
-        for count in (0..=0xFF).rev() {
-            let ch_code_asc = CH_CODE_ASC[count];
-            let mut bits_asc = self.state.ch_bits_asc[count];
-
-            if bits_asc <= 8 {
-                let add = 1u32 << bits_asc;
-                let mut acc = ch_code_asc as u32;
+    /// Collect statistics at the given level of detail
+    ///
+    /// Set it before reading, as it restarts the statistics.
+    pub fn with_stats_level(mut self, level: StatsLevel) -> Self {
+        self.state.stats = CompressionStats::with_level(level);
+        self
+    }
 
-                while acc < 0x100 {
-                    if (acc as usize) < self.state.offs_2c34.len() {
-                        self.state.offs_2c34[acc as usize] = count as u8;
-                    }
-                    acc += add;
-                }
-            } else if (ch_code_asc & 0xFF) != 0 {
-                let acc = (ch_code_asc & 0xFF) as usize;
-                if acc < self.state.offs_2c34.len() {
-                    self.state.offs_2c34[acc] = 0xFF;
-                }
+    /// Statistics of the data decompressed so far
+    ///
+    /// `input_bytes` counts the compressed bytes decoded, and becomes exact
+    /// like `consumed()` once the end of the stream has been reached.
+    pub fn stats(&self) -> CompressionStats {
+        let mut stats = self.state.stats.clone();
+        stats.input_bytes = if self.finished {
+            self.consumed()
+        } else if self.initialized {
+            self.state.bit_position().div_ceil(8)
+        } else {
+            0
+        };
+        stats.output_bytes = self.total_out;
+        stats.bytes_processed = self.total_out as usize;
+        stats.compression_ratio = CompressionStats::ratio(stats.input_bytes, stats.output_bytes);
+        stats.crc32 = self.crc32;
+        stats
+    }
 
-                if (ch_code_asc & 0x3F) != 0 {
-                    bits_asc -= 4;
-                    self.state.ch_bits_asc[count] = bits_asc;
+    /// Number of compressed bytes the stream occupied
+    ///
+    /// Only exact once the end of the stream has been reached, which is when
+    /// `read()` returns 0.
+    pub fn consumed(&self) -> u64 {
+        self.state.consumed_input()
+    }
 
-                    let add = 1u32 << bits_asc;
-                    let mut acc = (ch_code_asc >> 4) as u32;
-                    while acc < 0x100 {
-                        if (acc as usize) < self.state.offs_2d34.len() {
-                            self.state.offs_2d34[acc as usize] = count as u8;
-                        }
-                        acc += add;
-                    }
-                } else {
-                    bits_asc -= 6;
-                    self.state.ch_bits_asc[count] = bits_asc;
+    /// Consume the reader, returning the underlying reader and the input that
+    /// was read from it past the end of the stream
+    ///
+    /// The reader reads ahead up to 2 KB of input, so the underlying reader is
+    /// positioned somewhat after the end of the compressed stream. Together,
+    /// the returned bytes and the rest of the underlying reader form the data
+    /// that followed the stream. Call this once `read()` has returned 0.
+    pub fn into_inner_with_remainder(self) -> (R, Vec<u8>) {
+        let remainder = self.state.unread_input().to_vec();
+        (self.reader, remainder)
+    }
 
-                    let add = 1u32 << bits_asc;
-                    let mut acc = (ch_code_asc >> 6) as u32;
-                    while acc < 0x80 {
-                        if (acc as usize) < self.state.offs_2e34.len() {
-                            self.state.offs_2e34[acc as usize] = count as u8;
-                        }
-                        acc += add;
-                    }
-                }
-            } else {
-                bits_asc -= 8;
-                self.state.ch_bits_asc[count] = bits_asc;
+    /// Result of reading past the decompressed data: the end of the stream,
+    /// or the error that stopped decoding
+    fn end_of_data(&self) -> std::io::Result<usize> {
+        match &self.error {
+            Some(error) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                error.clone(),
+            )),
+            None => Ok(0),
+        }
+    }
 
-                let add = 1u32 << bits_asc;
-                let mut acc = (ch_code_asc >> 8) as u32;
-                while acc < 0x100 {
-                    if (acc as usize) < self.state.offs_2eb4.len() {
-                        self.state.offs_2eb4[acc as usize] = count as u8;
-                    }
-                    acc += add;
-                }
-            }
+    /// Initialize the reader by reading and parsing the header
+    fn initialize(&mut self) -> Result<()> {
+        if self.initialized {
+            return Ok(());
         }
+
+        self.state.read_header(&mut self.reader)?;
+        self.initialized = true;
+        Ok(())
     }
 
-    /// Main expansion logic - port of Expand function from PKLib
+    /// Main expansion logic - decompress the next block into the output buffer
     fn expand(&mut self) -> Result<usize> {
         if !self.initialized {
             self.initialize()?;
@@ -183,115 +166,37 @@ impl<R: Read> ExplodeReader<R> {
             return Ok(0);
         }
 
-        let mut bytes_written = 0;
-        self.state.output_pos = 0x1000; // Initialize output buffer position
-
-        // Main decompression loop
-        loop {
-            let next_literal = self.state.decode_lit(&mut self.reader)?;
-
-            match next_literal {
-                // End of stream
-                LITERAL_END_OF_STREAM => {
-                    self.finished = true;
-                    break;
-                }
-
-                // Error
-                LITERAL_ERROR => {
-                    return Err(PkLibError::DecompressionError("Decode error".to_string()));
-                }
-
-                // Repetition (length encoded as literal >= 0x100)
-                literal if literal >= 0x100 => {
-                    // Calculate repetition length
-                    let rep_length = literal - 0xFE;
-
-                    // Get backward distance to repetition
-                    let minus_dist = self.state.decode_dist(&mut self.reader, rep_length)?;
-                    if minus_dist == 0 {
-                        return Err(PkLibError::DecompressionError(
-                            "Invalid distance".to_string(),
-                        ));
-                    }
-
-                    // Calculate source and target positions
-                    let target_pos = self.state.output_pos;
-                    let source_pos = target_pos.saturating_sub(minus_dist as usize);
-
-                    // Bounds checking
-                    if source_pos >= self.state.out_buff.len()
-                        || target_pos + rep_length as usize > self.state.out_buff.len()
-                    {
-                        return Err(PkLibError::DecompressionError(
-                            "Buffer overflow".to_string(),
-                        ));
-                    }
-
-                    // Copy the repeating sequence (may overlap)
-                    for i in 0..rep_length as usize {
-                        if target_pos + i < self.state.out_buff.len()
-                            && source_pos + i < self.state.out_buff.len()
-                        {
-                            self.state.out_buff[target_pos + i] =
-                                self.state.out_buff[source_pos + i];
-                        }
-                    }
-
-                    self.state.output_pos += rep_length as usize;
-                }
-
-                // Literal byte (< 0x100)
-                literal => {
-                    if self.state.output_pos < self.state.out_buff.len() {
-                        self.state.out_buff[self.state.output_pos] = literal as u8;
-                        self.state.output_pos += 1;
-                    } else {
-                        return Err(PkLibError::DecompressionError(
-                            "Output buffer overflow".to_string(),
-                        ));
-                    }
-                }
+        // The output position persists across calls: the first 0x1000 bytes of
+        // out_buff hold the dictionary for repetitions spanning a flush
+        self.finished = match self.state.expand_block(&mut self.reader) {
+            Ok(finished) => finished,
+            // Stop here, keeping the error for the read after this block
+            Err(error @ PkLibError::OutputLimitExceeded { .. }) => {
+                self.error = Some(error);
+                true
             }
-
-            // Flush output buffer when it reaches capacity
-            if self.state.output_pos >= 0x2000 {
-                // Copy decompressed data from second half of buffer to output
-                let copy_start = 0x1000;
-                let copy_end = self.state.output_pos.min(0x2000);
-
-                if copy_end > copy_start {
-                    self.output_buffer
-                        .extend_from_slice(&self.state.out_buff[copy_start..copy_end]);
-                    bytes_written += copy_end - copy_start;
-                }
-
-                // Move remaining data to first half (for repetition references)
-                let remaining_bytes = self.state.output_pos - 0x1000;
-                if remaining_bytes > 0 && remaining_bytes <= 0x1000 {
-                    self.state
-                        .out_buff
-                        .copy_within(0x1000..self.state.output_pos, 0);
-                }
-                self.state.output_pos = remaining_bytes;
-
-                // Return what we have so far
-                if bytes_written > 0 {
-                    break;
-                }
+            // Hand out the tokens decoded before the error, then report it
+            Err(error) if self.recover => {
+                self.error = Some(error);
+                true
             }
-        }
-
-        // Flush any remaining data
-        if self.finished && self.state.output_pos > 0x1000 {
-            let copy_start = 0x1000;
-            let copy_end = self.state.output_pos;
+            Err(error) => return Err(error),
+        };
 
-            if copy_end > copy_start {
-                self.output_buffer
-                    .extend_from_slice(&self.state.out_buff[copy_start..copy_end]);
-                bytes_written += copy_end - copy_start;
+        let block = match self.error {
+            // The token that passed the limit may have run past the block
+            Some(PkLibError::OutputLimitExceeded { limit }) => {
+                &self.state.output_tail()[..(limit - self.total_out) as usize]
             }
+            _ => self.state.output_block(),
+        };
+        let bytes_written = block.len();
+        self.total_out += bytes_written as u64;
+        self.crc32 = crc32_pklib(block, self.crc32);
+        self.output_buffer.extend_from_slice(block);
+
+        if !self.finished {
+            self.state.next_block();
         }
 
         Ok(bytes_written)
@@ -318,12 +223,12 @@ impl<R: Read> Read for ExplodeReader<R> {
         }
 
         if self.finished {
-            return Ok(0);
+            return self.end_of_data();
         }
 
         // Expand more data
         match self.expand() {
-            Ok(0) => Ok(0), // EOF
+            Ok(0) => self.end_of_data(), // EOF
             Ok(_) => {
                 // Try to return data from newly expanded buffer
                 if self.output_pos < self.output_buffer.len() {
@@ -338,7 +243,13 @@ impl<R: Read> Read for ExplodeReader<R> {
                     Ok(0)
                 }
             }
-            Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            // Decoding can't continue from the bit state it failed in, so
+            // later reads report the same error
+            Err(e) => {
+                self.finished = true;
+                self.error = Some(e.clone());
+                Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+            }
         }
     }
 }
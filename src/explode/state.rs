@@ -10,7 +10,10 @@ use super::{
 use crate::tables::{
     CH_BITS_ASC, CH_CODE_ASC, DIST_BITS, DIST_CODE, EX_LEN_BITS, LEN_BASE, LEN_BITS, LEN_CODE,
 };
-use crate::{CompressionMode, PkLibError, Result};
+use crate::{
+    CompressionHeader, CompressionMode, CompressionStats, DictionarySize, PkLibError, Result,
+    TokenKind,
+};
 
 /// Decompression state structure matching PKLib's TDcmpStruct
 #[derive(Debug)]
@@ -32,6 +35,27 @@ pub struct ExplodeState {
     pub in_pos: usize,
     /// Number of bytes available in input buffer
     pub in_bytes: usize,
+    /// Total number of bytes read into the input buffer
+    pub in_total: u64,
+    /// Whether the stream has to end with an end of stream marker
+    pub require_end_marker: bool,
+    /// Whether the input ended after the last decoded token, for streams
+    /// without an end marker
+    pub input_ended: bool,
+    /// Total number of bytes decompressed
+    pub out_total: u64,
+    /// Number of preset dictionary bytes in front of the decompressed data
+    pub dict_len: usize,
+    /// Whether repetitions reaching before the start of the data are rejected
+    pub strict_distances: bool,
+    /// Number of decompressed bytes after which decoding stops
+    pub max_output: Option<u64>,
+    /// Part of the token currently being decoded
+    pub token_kind: TokenKind,
+    /// Bit offset in the stream where that part starts
+    pub token_start: u64,
+    /// Token counts of the data decompressed so far
+    pub stats: CompressionStats,
 
     // Buffers
     /// Output circular buffer
@@ -78,6 +102,16 @@ impl ExplodeState {
             extra_bits: 0,
             in_pos: 0,
             in_bytes: 0,
+            in_total: 0,
+            require_end_marker: true,
+            input_ended: false,
+            out_total: 0,
+            dict_len: 0,
+            strict_distances: true,
+            max_output: None,
+            token_kind: TokenKind::Literal,
+            token_start: 0,
+            stats: CompressionStats::default(),
             out_buff: [0; OUT_BUFF_SIZE],
             in_buff: [0; IN_BUFF_SIZE],
             dist_pos_codes: [0; CODES_SIZE],
@@ -138,6 +172,28 @@ impl ExplodeState {
         Ok(())
     }
 
+    /// Compression mode and dictionary size read from the header
+    pub fn header(&self) -> Option<CompressionHeader> {
+        Some(CompressionHeader {
+            mode: self.ctype,
+            dict_size: DictionarySize::from_bits(self.dsize_bits as u8).ok()?,
+            uncompressed_size: None,
+            crc32: None,
+        })
+    }
+
+    /// Prime the output window with a preset dictionary
+    ///
+    /// The dictionary ends right where the decompressed data begins, so that
+    /// repetitions at the start of the stream can refer to it. Only the last
+    /// 4096 bytes can be referenced.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        let len = dictionary.len().min(self.output_pos);
+        self.out_buff[self.output_pos - len..self.output_pos]
+            .copy_from_slice(&dictionary[dictionary.len() - len..]);
+        self.dict_len = len;
+    }
+
     /// Generate decode tables (port of GenDecodeTabs from PKLib)
     fn gen_decode_tabs(positions: &mut [u8], start_indexes: &[u8], length_bits: &[u8]) {
         for i in 0..start_indexes.len() {
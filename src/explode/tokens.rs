@@ -0,0 +1,135 @@
+//! TokenReader - Inspection of the tokens in a compressed stream
+//!
+//! This module decodes the literals and repetitions of a compressed stream
+//! without expanding them, reporting where each one is stored. It is meant
+//! for analysis tools, such as comparing the choices of different encoders.
+
+use super::{state::ExplodeState, LITERAL_END_OF_STREAM, LITERAL_ERROR};
+use crate::{CompressionHeader, PkLibError, Result};
+use std::io::Read;
+
+/// A token of a compressed stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    /// A literal byte
+    Literal(u8),
+    /// A repetition of earlier data
+    Match {
+        /// Number of bytes repeated (2 to 516)
+        length: u16,
+        /// Number of bytes back from the current position where the repeated
+        /// data starts (1 is the previous byte)
+        distance: u16,
+    },
+    /// The end of stream marker
+    End,
+}
+
+/// A token together with its location in the compressed stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenInfo {
+    /// The decoded token
+    pub token: Token,
+    /// Offset of the token's first bit, counted from the start of the stream
+    /// (including the header)
+    pub bit_offset: u64,
+    /// Number of bits the token occupies
+    pub bit_length: u32,
+}
+
+/// Iterator over the tokens of a compressed stream
+///
+/// Yields every literal and repetition in stream order, followed by
+/// `Token::End`. If the stream is damaged, the error is yielded instead and
+/// iteration stops.
+#[derive(Debug)]
+pub struct TokenReader<R: Read> {
+    reader: R,
+    state: ExplodeState,
+    initialized: bool,
+    finished: bool,
+}
+
+impl<R: Read> TokenReader<R> {
+    /// Create a new TokenReader
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            state: ExplodeState::new(),
+            initialized: false,
+            finished: false,
+        }
+    }
+
+    /// Compression mode and dictionary size of the stream, once its header
+    /// has been read
+    pub fn header(&self) -> Option<CompressionHeader> {
+        if !self.initialized {
+            return None;
+        }
+        self.state.header()
+    }
+
+    /// Decode the next token
+    fn next_token(&mut self) -> Result<TokenInfo> {
+        if !self.initialized {
+            self.state.read_header(&mut self.reader)?;
+            self.initialized = true;
+        }
+
+        let state = &mut self.state;
+        let bit_offset = state.bit_position();
+
+        let token = match state.decode_lit(&mut self.reader)? {
+            LITERAL_END_OF_STREAM => Token::End,
+            LITERAL_ERROR => {
+                return Err(PkLibError::UnexpectedEof.at(state.failure_position()));
+            }
+            literal if literal >= 0x100 => {
+                let length = literal - 0xFE;
+                let distance = state.decode_dist(&mut self.reader, length)?;
+                if distance == 0 {
+                    return Err(PkLibError::UnexpectedEof.at(state.failure_position()));
+                }
+                state.out_total += length as u64;
+                Token::Match {
+                    length: length as u16,
+                    distance: distance as u16,
+                }
+            }
+            literal => {
+                state.out_total += 1;
+                Token::Literal(literal as u8)
+            }
+        };
+
+        // The end marker may end exactly with the input, in which case the
+        // bit reader stops short of it: 1 flag bit, the length code and the
+        // extra bits of the largest length
+        let bit_length = if token == Token::End && state.in_bytes == 0 {
+            1 + state.len_bits[0x0F] as u32 + state.ex_len_bits[0x0F] as u32
+        } else {
+            (state.bit_position() - bit_offset) as u32
+        };
+
+        Ok(TokenInfo {
+            token,
+            bit_offset,
+            bit_length,
+        })
+    }
+}
+
+impl<R: Read> Iterator for TokenReader<R> {
+    type Item = Result<TokenInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let result = self.next_token();
+        self.finished = !matches!(result, Ok(TokenInfo { token, .. }) if token != Token::End);
+        Some(result)
+    }
+}
@@ -0,0 +1,253 @@
+//! Bit encoding and literal/repetition encoding functions
+//!
+//! This module implements the output side of PKLib implode: the main loop of
+//! WriteCmpData, the encoding of literals and repetitions, and the bit output
+//! buffer. Complete output is handed to a sink, so the same code serves the
+//! streaming writer and the slice-based API.
+
+use super::{pattern::MatchResult, state::ImplodeState};
+use crate::{CompressionLevel, PkLibError, Result};
+
+impl ImplodeState {
+    /// Set up the output buffer with the stream header like PKLib
+    pub fn begin_output(&mut self) {
+        // PKLib initializes the output buffer with compression type and dictionary size
+        // Store the compression type and dictionary size (PKLib lines 419-421)
+        self.out_buff[0] = self.ctype as u8;
+        self.out_buff[1] = self.dsize_bits as u8;
+        self.out_bytes = 2;
+
+        // Reset output buffer from position 2 onwards (PKLib lines 424-425)
+        self.out_buff[2..].fill(0);
+        self.out_bits = 0;
+    }
+
+    /// Compress the current input block (port of the WriteCmpData main loop)
+    ///
+    /// Non-final blocks keep their last `MAX_REP_LENGTH` bytes as lookahead and
+    /// slide the window afterwards; the final block compresses everything.
+    pub fn compress_block<F>(&mut self, input_ended: bool, sink: &mut F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        self.begin_block(input_ended);
+
+        // Repetition already found at the current position by the lookahead
+        let mut next_match = None;
+
+        // The best level plans the whole block up front
+        if self.level == CompressionLevel::Best {
+            for chosen in self.optimal_parse() {
+                self.encode_step(chosen, sink)?;
+            }
+        }
+
+        while self.work_pos < self.input_end {
+            let input_pos = self.work_pos;
+            let chosen = self.select_repetition(input_pos, input_ended, &mut next_match);
+            self.encode_step(chosen, sink)?;
+        }
+
+        if !input_ended {
+            self.slide_window();
+        }
+
+        Ok(())
+    }
+
+    /// Encode a repetition, or a literal for `None`, and move past it
+    fn encode_step<F>(&mut self, chosen: Option<MatchResult>, sink: &mut F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        match chosen {
+            Some(match_result) => {
+                self.encode_match(match_result, sink)?;
+                self.work_pos += match_result.length;
+            }
+            None => {
+                self.encode_literal(self.work_buff[self.work_pos], sink)?;
+                self.work_pos += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode a literal byte
+    pub fn encode_literal<F>(&mut self, byte: u8, sink: &mut F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        self.stats.record_literal(byte);
+
+        let literal_index = byte as usize;
+        let bits = self.literal_bits[literal_index];
+        let code = self.literal_codes[literal_index] as u32;
+        self.output_bits(bits as u32, code, sink)
+    }
+
+    /// Encode a length/distance match
+    pub fn encode_match<F>(&mut self, match_result: MatchResult, sink: &mut F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        let length = match_result.length;
+        let distance = match_result.distance;
+
+        // Encode length (PKLib uses length + 0xFE for the encoding)
+        let length_code = length + 0xFE;
+        if length_code < self.literal_bits.len() {
+            let bits = self.literal_bits[length_code];
+            let code = self.literal_codes[length_code] as u32;
+            self.output_bits(bits as u32, code, sink)?;
+        } else {
+            return Err(PkLibError::InvalidLength(length as u32));
+        }
+
+        // Encode distance
+        let dist_minus_one = (distance - 1) as u32; // PKLib stores distance - 1
+        if length == 2 {
+            // For 2-byte repetitions, use special encoding
+            let dist_code_index = (dist_minus_one >> 2) as usize;
+            if dist_code_index < self.dist_bits.len() {
+                let bits = self.dist_bits[dist_code_index];
+                let code = self.dist_codes[dist_code_index] as u32;
+                self.output_bits(bits as u32, code, sink)?;
+                self.output_bits(2, dist_minus_one & 3, sink)?;
+            } else {
+                return Err(PkLibError::InvalidDistance(distance as u32));
+            }
+        } else {
+            // For longer repetitions, use dictionary size bits
+            let dist_code_index = (dist_minus_one >> self.dsize_bits) as usize;
+            if dist_code_index < self.dist_bits.len() {
+                let bits = self.dist_bits[dist_code_index];
+                let code = self.dist_codes[dist_code_index] as u32;
+                self.output_bits(bits as u32, code, sink)?;
+                self.output_bits(self.dsize_bits, dist_minus_one & self.dsize_mask, sink)?;
+            } else {
+                return Err(PkLibError::InvalidDistance(distance as u32));
+            }
+        }
+
+        self.stats.record_match(length, distance, self.dsize_bits);
+        Ok(())
+    }
+
+    /// Output bits to the compressed stream (exact port of OutputBits from PKLib)
+    fn output_bits<F>(&mut self, mut n_bits: u32, mut bit_buffer: u32, sink: &mut F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        // If more than 8 bits to output, do recursion (exactly like PKLib)
+        if n_bits > 8 {
+            self.output_bits(8, bit_buffer, sink)?;
+            bit_buffer >>= 8;
+            n_bits -= 8;
+            return self.output_bits(n_bits, bit_buffer, sink);
+        }
+
+        // Add bits to the last out byte in out_buff (PKLib line 124)
+        let out_bits = self.out_bits;
+        let out_bytes = self.out_bytes as usize;
+
+        // Ensure we have space in the buffer
+        if out_bytes >= self.out_buff.len() {
+            self.flush_output(sink)?;
+            return self.output_bits(n_bits, bit_buffer, sink);
+        }
+
+        // PKLib: pWork->out_buff[pWork->out_bytes] |= (unsigned char)(bit_buff << out_bits);
+        self.out_buff[out_bytes] |= ((bit_buffer << out_bits) & 0xFF) as u8;
+        self.out_bits += n_bits;
+
+        // If 8 or more bits, increment number of bytes (PKLib lines 128-141)
+        if self.out_bits > 8 {
+            self.out_bytes += 1;
+
+            // PKLib: bit_buff >>= (8 - out_bits);
+            bit_buffer >>= 8 - out_bits;
+
+            // Ensure we have space for the next byte
+            let new_out_bytes = self.out_bytes as usize;
+            if new_out_bytes < self.out_buff.len() {
+                // PKLib: pWork->out_buff[pWork->out_bytes] = (unsigned char)bit_buff;
+                self.out_buff[new_out_bytes] = (bit_buffer & 0xFF) as u8;
+            }
+
+            // PKLib: pWork->out_bits &= 7;
+            self.out_bits &= 7;
+        } else {
+            // PKLib: pWork->out_bits &= 7;
+            self.out_bits &= 7;
+            if self.out_bits == 0 {
+                self.out_bytes += 1;
+            }
+        }
+
+        // Stop as soon as the output, counting a partly filled byte, reaches
+        // the limit: it can only grow from here
+        if let Some(limit) = self.max_output {
+            let written =
+                self.stats.output_bytes + self.out_bytes as u64 + u64::from(self.out_bits > 0);
+            if written >= limit {
+                return Err(PkLibError::BufferTooSmall);
+            }
+        }
+
+        // If there is enough compressed bytes, flush them (PKLib lines 144-145)
+        if self.out_bytes >= 0x800 {
+            self.flush_output(sink)?;
+        }
+
+        Ok(())
+    }
+
+    /// Hand the complete bytes of the output buffer to the sink
+    pub fn flush_output<F>(&mut self, sink: &mut F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        if self.out_bytes > 0 {
+            let bytes_to_write = self.out_bytes as usize;
+            if bytes_to_write <= self.out_buff.len() {
+                sink(&self.out_buff[..bytes_to_write])?;
+                self.stats.output_bytes += bytes_to_write as u64;
+
+                // Clear the buffer but preserve any partial byte
+                let save_byte = if self.out_bits > 0 && bytes_to_write < self.out_buff.len() {
+                    self.out_buff[bytes_to_write]
+                } else {
+                    0
+                };
+
+                self.out_buff.fill(0);
+                self.out_bytes = 0;
+
+                if self.out_bits > 0 {
+                    self.out_buff[0] = save_byte;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write end-of-stream marker
+    pub fn write_end_marker<F>(&mut self, sink: &mut F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        // PKLib uses literal code 0x305 as end marker
+        const END_MARKER: usize = 0x305;
+        let bits = self.literal_bits[END_MARKER];
+        let code = self.literal_codes[END_MARKER] as u32;
+        self.output_bits(bits as u32, code, sink)?;
+
+        // Flush any remaining bits
+        if self.out_bits > 0 {
+            self.out_bytes += 1;
+        }
+
+        Ok(())
+    }
+}
@@ -7,11 +7,23 @@
 use super::{byte_pair_hash, state::ImplodeState, HASH_TABLE_SIZE};
 
 impl ImplodeState {
+    /// Hash of the byte pair starting at `pos` in the work buffer
+    ///
+    /// PKLib hashes one position past the end of the data, which may read
+    /// beyond the work buffer (see `window_byte`).
+    pub(crate) fn pair_hash_at(&self, pos: usize) -> usize {
+        byte_pair_hash(&[self.window_byte(pos), self.window_byte(pos + 1)])
+    }
+
     /// Build hash table for the current work buffer
     /// This is a port of the SortBuffer function from PKLib implode.c
+    ///
+    /// Every position in `buffer_begin..buffer_end` is hashed, and the offsets
+    /// stored in `phash_offs` are absolute work buffer positions. Offsets of each
+    /// PAIR_HASH are grouped together in ascending order, and `phash_to_index`
+    /// points to the first offset of each group.
     pub fn sort_buffer(&mut self, buffer_begin: usize, buffer_end: usize) {
-        // Ensure we have at least 2 bytes for pair hash
-        if buffer_end <= buffer_begin + 1 {
+        if buffer_end <= buffer_begin {
             return;
         }
 
@@ -19,52 +31,34 @@ impl ImplodeState {
         self.phash_to_index.fill(0);
 
         // Step 2: Count occurrences of each PAIR_HASH in the input buffer
-        // The table will contain the number of occurrences of each hash value
-        for pos in buffer_begin..buffer_end - 1 {
-            if pos + 1 < self.work_buff.len() {
-                let hash = byte_pair_hash(&self.work_buff[pos..pos + 2]);
-                if hash < HASH_TABLE_SIZE {
-                    self.phash_to_index[hash] = self.phash_to_index[hash].saturating_add(1);
-                }
-            }
+        for pos in buffer_begin..buffer_end {
+            let hash = self.pair_hash_at(pos);
+            self.phash_to_index[hash] = self.phash_to_index[hash].wrapping_add(1);
         }
 
         // Step 3: Convert the table to cumulative counts
         // Each element contains count of PAIR_HASHes that is less than or equal to element index
         let mut total_sum = 0u16;
         for hash_count in &mut self.phash_to_index {
-            total_sum = total_sum.saturating_add(*hash_count);
+            total_sum = total_sum.wrapping_add(*hash_count);
             *hash_count = total_sum;
         }
 
         // Step 4: Build the offset table by processing buffer in reverse
-        // This creates a table where each PAIR_HASH points to its first occurrence
-        for pos in (buffer_begin..buffer_end - 1).rev() {
-            if pos + 1 < self.work_buff.len() {
-                let hash = byte_pair_hash(&self.work_buff[pos..pos + 2]);
-                if hash < HASH_TABLE_SIZE {
-                    // Decrement the count to get the index
-                    self.phash_to_index[hash] = self.phash_to_index[hash].saturating_sub(1);
-
-                    let index = self.phash_to_index[hash] as usize;
-                    if index < self.phash_offs.len() {
-                        // Store the relative offset from work_buff start
-                        self.phash_offs[index] = (pos - buffer_begin) as u16;
-                    }
-                }
-            }
+        // This leaves each PAIR_HASH pointing to its first occurrence
+        for pos in (buffer_begin..buffer_end).rev() {
+            let hash = self.pair_hash_at(pos);
+            self.phash_to_index[hash] = self.phash_to_index[hash].wrapping_sub(1);
+
+            let index = self.phash_to_index[hash] as usize;
+            self.phash_offs[index] = pos as u16;
         }
     }
 
-    /// Get the first occurrence index for a given hash value
+    /// Get the index of the first offset stored for a given hash value
     pub fn get_hash_index(&self, hash: usize) -> Option<usize> {
         if hash < HASH_TABLE_SIZE {
-            let index = self.phash_to_index[hash] as usize;
-            if index < self.phash_offs.len() && self.phash_offs[index] != 0 {
-                Some(index)
-            } else {
-                None
-            }
+            Some(self.phash_to_index[hash] as usize)
         } else {
             None
         }
@@ -72,64 +66,11 @@ impl ImplodeState {
 
     /// Get the offset for a given index in the hash offset table
     pub fn get_hash_offset(&self, index: usize) -> Option<usize> {
-        if index < self.phash_offs.len() {
-            let offset = self.phash_offs[index] as usize;
-            if offset > 0 {
-                Some(offset)
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    }
-
-    /// Find all positions where a specific byte pair hash occurs
-    pub fn find_hash_positions(&self, hash: usize, current_pos: usize) -> Vec<usize> {
-        let mut positions = Vec::new();
-
-        if let Some(start_index) = self.get_hash_index(hash) {
-            let min_offset = current_pos.saturating_sub(self.dsize_bytes as usize);
-
-            // Walk through all occurrences of this hash
-            for i in start_index..self.phash_offs.len() {
-                if let Some(offset) = self.get_hash_offset(i) {
-                    // Check if this offset is within our dictionary window
-                    if offset >= min_offset && offset < current_pos {
-                        positions.push(offset);
-                    } else if offset >= current_pos {
-                        // Offsets are sorted, so we can stop here
-                        break;
-                    }
-                } else {
-                    break; // No more valid offsets
-                }
-            }
-        }
-
-        positions
-    }
-
-    /// Update hash table incrementally for a new byte pair
-    /// This is used when sliding the compression window
-    pub fn update_hash_incremental(&mut self, pos: usize) {
-        if pos + 1 < self.work_buff.len() {
-            let hash = byte_pair_hash(&self.work_buff[pos..pos + 2]);
-            if hash < HASH_TABLE_SIZE {
-                // Find next available slot in the hash offset table
-                // This is a simplified incremental update - full PKLib does more complex management
-                for i in 0..self.phash_offs.len() {
-                    if self.phash_offs[i] == 0 {
-                        self.phash_offs[i] = pos as u16;
-                        break;
-                    }
-                }
-            }
-        }
+        self.phash_offs.get(index).map(|&offset| offset as usize)
     }
 
     /// Validate hash table consistency (for debugging)
-    #[cfg(debug_assertions)]
+    #[cfg(any(test, debug_assertions))]
     pub fn validate_hash_table(&self, buffer_start: usize, buffer_end: usize) -> bool {
         // Check that hash indices are within bounds
         for &index in &self.phash_to_index {
@@ -138,17 +79,11 @@ impl ImplodeState {
             }
         }
 
-        // Check that offsets point to valid positions
-        for &offset in &self.phash_offs {
-            if offset != 0 {
-                let pos = offset as usize;
-                if pos < buffer_start || pos >= buffer_end {
-                    return false;
-                }
-            }
-        }
-
-        true
+        // Check that each group of offsets lies within the sorted range
+        let count = buffer_end.saturating_sub(buffer_start);
+        self.phash_offs[..count]
+            .iter()
+            .all(|&offset| (buffer_start..buffer_end).contains(&(offset as usize)))
     }
 }
 
@@ -166,7 +101,6 @@ mod tests {
     }
 
     #[test]
-    #[ignore] // TODO: Fix after compression refactoring
     fn test_sort_buffer_basic() {
         let mut state = ImplodeState::new(CompressionMode::Binary, DictionarySize::Size1K).unwrap();
 
@@ -177,18 +111,16 @@ mod tests {
 
         // Sort the buffer
         state.sort_buffer(0, len);
+        assert!(state.validate_hash_table(0, len));
 
-        // Verify hash table was built
+        // Both occurrences of "AB" are grouped together in ascending order
         let hash_ab = byte_pair_hash(b"AB");
-        assert!(state.get_hash_index(hash_ab).is_some());
-
-        // Verify we can find positions
-        let positions = state.find_hash_positions(hash_ab, len);
-        assert!(!positions.is_empty());
+        let index = state.get_hash_index(hash_ab).unwrap();
+        assert_eq!(state.get_hash_offset(index), Some(0));
+        assert_eq!(state.get_hash_offset(index + 1), Some(3));
     }
 
     #[test]
-    #[ignore] // TODO: Fix after compression refactoring
     fn test_hash_table_edge_cases() {
         let mut state = ImplodeState::new(CompressionMode::Binary, DictionarySize::Size1K).unwrap();
 
@@ -3,15 +3,21 @@
 //! This module provides compression functionality compatible with the PKWare DCL format.
 //! It implements the implode algorithm exactly as specified in the original PKLib.
 
+mod encoder;
 mod hash;
+mod optimal;
 mod pattern;
+mod push;
 mod state;
+mod tokens;
 mod writer;
 
+pub use push::ImplodeEncoder;
 pub use state::ImplodeState;
+pub use tokens::TokenEncoder;
 pub use writer::ImplodeWriter;
 
-use crate::Result;
+use crate::{PkLibError, Result};
 use std::io::Write;
 
 /// Work buffer size for compression (8708 bytes)
@@ -23,6 +29,9 @@ pub const OUT_BUFF_SIZE: usize = 0x802;
 /// Hash table size for pattern matching (2304 entries)
 pub const HASH_TABLE_SIZE: usize = 0x900;
 
+/// Number of input bytes PKLib loads into the work buffer per block (4096 bytes)
+pub const INPUT_BLOCK_SIZE: usize = 0x1000;
+
 /// Offset table size for compression optimization (516 bytes)
 pub const OFFSS_SIZE2: usize = 0x204;
 
@@ -43,12 +52,189 @@ pub fn implode_bytes(
     data: &[u8],
     mode: crate::CompressionMode,
     dict_size: crate::DictionarySize,
+) -> Result<Vec<u8>> {
+    implode_bytes_with_level(data, mode, dict_size, crate::CompressionLevel::Default)
+}
+
+/// Convenience function to compress data in memory at a given compression level
+pub fn implode_bytes_with_level(
+    data: &[u8],
+    mode: crate::CompressionMode,
+    dict_size: crate::DictionarySize,
+    level: crate::CompressionLevel,
 ) -> Result<Vec<u8>> {
     let mut output = Vec::new();
     {
-        let mut writer = ImplodeWriter::new(&mut output, mode, dict_size)?;
+        let mut writer = ImplodeWriter::with_level(&mut output, mode, dict_size, level)?;
         writer.write_all(data)?;
         writer.finish()?;
     }
     Ok(output)
 }
+
+/// Convenience function to compress data in memory, choosing the compression
+/// mode with `CompressionMode::detect`
+pub fn implode_bytes_auto(data: &[u8], dict_size: crate::DictionarySize) -> Result<Vec<u8>> {
+    implode_bytes(data, crate::CompressionMode::detect(data), dict_size)
+}
+
+/// Compress data with every mode and dictionary size, keeping the smallest
+/// output
+///
+/// All six combinations are compressed in parallel at
+/// `CompressionLevel::Best`. The smallest stream that `explode_bytes`
+/// decompresses back to `data` is returned together with its settings.
+pub fn implode_best(
+    data: &[u8],
+) -> Result<(Vec<u8>, crate::CompressionMode, crate::DictionarySize)> {
+    use crate::{CompressionLevel, CompressionMode, DictionarySize};
+
+    let mut combinations = Vec::new();
+    for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
+        for dict_size in [
+            DictionarySize::Size1K,
+            DictionarySize::Size2K,
+            DictionarySize::Size4K,
+        ] {
+            combinations.push((mode, dict_size));
+        }
+    }
+
+    let mut candidates = std::thread::scope(|scope| {
+        let handles: Vec<_> = combinations
+            .iter()
+            .map(|&(mode, dict_size)| {
+                scope.spawn(move || {
+                    implode_bytes_with_level(data, mode, dict_size, CompressionLevel::Best)
+                        .map(|compressed| (compressed, mode, dict_size))
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect::<Result<Vec<_>>>()
+    })?;
+
+    // Ties keep the earlier combination, as the sort is stable
+    candidates.sort_by_key(|(compressed, _, _)| compressed.len());
+
+    let mut error = None;
+    for candidate in candidates {
+        match crate::explode::explode_bytes(&candidate.0) {
+            Ok(decompressed) if decompressed == data => return Ok(candidate),
+            Ok(_) => {
+                error = Some(PkLibError::InvalidData(
+                    "Compressed data does not decompress to the input".to_string(),
+                ))
+            }
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(error.unwrap_or(PkLibError::InvalidFormat))
+}
+
+/// Compress data from one slice into another without allocating
+///
+/// Returns the number of bytes written to `dst`, or
+/// `PkLibError::BufferTooSmall` if the compressed data doesn't fit.
+pub fn implode_into(
+    src: &[u8],
+    mode: crate::CompressionMode,
+    dict_size: crate::DictionarySize,
+    dst: &mut [u8],
+) -> Result<usize> {
+    let mut written = 0;
+    implode_with_sink(src, mode, dict_size, None, |bytes: &[u8]| {
+        let end = written + bytes.len();
+        dst.get_mut(written..end)
+            .ok_or(PkLibError::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        written = end;
+        Ok(())
+    })?;
+
+    Ok(written)
+}
+
+/// Compress data unless the compressed data is at least `max_out` bytes
+///
+/// Compression stops as soon as the output reaches `max_out` bytes, without
+/// compressing the rest of the input, and `None` is returned. Pass the input
+/// length as `max_out` to get `None` whenever compression doesn't shrink the
+/// data, for formats that then store it uncompressed.
+pub fn implode_bounded(
+    data: &[u8],
+    mode: crate::CompressionMode,
+    dict_size: crate::DictionarySize,
+    max_out: usize,
+) -> Result<Option<Vec<u8>>> {
+    let mut output = Vec::new();
+    let result = implode_with_sink(data, mode, dict_size, Some(max_out as u64), |bytes| {
+        output.extend_from_slice(bytes);
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => Ok(Some(output)),
+        Err(PkLibError::BufferTooSmall) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Largest possible compressed size of `len` bytes of input
+///
+/// Holds for every compression level and dictionary size. In the worst case
+/// every byte is a literal, which takes 9 bits in Binary mode and up to 14
+/// bits in ASCII mode; repetitions never take more than 7 bits per byte. The
+/// 2 header bytes and the 16-bit end marker come on top. Saturates at
+/// `usize::MAX` for lengths whose bound doesn't fit.
+pub const fn implode_bound(len: usize, mode: crate::CompressionMode) -> usize {
+    let literal_bits = match mode {
+        crate::CompressionMode::Binary => 9,
+        crate::CompressionMode::ASCII => 14,
+    };
+    let bits = len.saturating_mul(literal_bits).saturating_add(16);
+    bits.div_ceil(8).saturating_add(2)
+}
+
+/// Compress `src` in one go, handing the compressed data to `sink`
+///
+/// Fails with `PkLibError::BufferTooSmall` once the output reaches
+/// `max_output` bytes.
+fn implode_with_sink<F>(
+    src: &[u8],
+    mode: crate::CompressionMode,
+    dict_size: crate::DictionarySize,
+    max_output: Option<u64>,
+    mut sink: F,
+) -> Result<()>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    let mut state = ImplodeState::new(mode, dict_size)?;
+    state.max_output = max_output;
+    state.begin_output();
+
+    // Compress each block as soon as it is full, like ImplodeWriter does
+    let mut remaining = src;
+    while !remaining.is_empty() {
+        let loaded = state.load_input(remaining);
+        remaining = &remaining[loaded..];
+
+        if state.block_full() {
+            state.compress_block(false, &mut sink)?;
+        }
+    }
+
+    if state.has_pending_input() {
+        state.compress_block(true, &mut sink)?;
+    }
+    state.write_end_marker(&mut sink)?;
+    state.flush_output(&mut sink)
+}
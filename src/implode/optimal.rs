@@ -0,0 +1,189 @@
+//! Bit-cost optimal parsing
+//!
+//! This module chooses the literals and repetitions of an input block by their
+//! total encoded size. PKLib's WriteCmpData writes the longest repetition it
+//! finds; here every literal and repetition at each position is weighed by the
+//! exact number of bits it takes in the output stream, and the cheapest
+//! sequence for the whole block is chosen.
+
+use super::{pattern::MatchResult, state::ImplodeState};
+
+/// Repetitions at least this long are taken as a whole
+const NICE_LENGTH: usize = 32;
+
+impl ImplodeState {
+    /// Number of bits needed to write the byte at `pos` as a literal
+    fn literal_cost(&self, pos: usize) -> u32 {
+        self.literal_bits[self.work_buff[pos] as usize] as u32
+    }
+
+    /// Number of bits needed to write a repetition
+    fn repetition_cost(&self, length: usize, distance: usize) -> u32 {
+        let length_bits = self.literal_bits[length + 0xFE] as u32;
+        let dist_minus_one = distance - 1;
+        if length == 2 {
+            length_bits + self.dist_bits[dist_minus_one >> 2] as u32 + 2
+        } else {
+            length_bits + self.dist_bits[dist_minus_one >> self.dsize_bits] as u32 + self.dsize_bits
+        }
+    }
+
+    /// Choose the cheapest sequence of literals and repetitions for the
+    /// current block
+    ///
+    /// Returns one entry per step from `work_pos` up to `input_end`, with
+    /// `None` standing for a literal. Like in PKLib, the last repetition may
+    /// reach into the lookahead of the next block. Each repetition uses the
+    /// distance of the longest one found at its position, so the result is
+    /// near-optimal rather than strictly optimal.
+    pub(crate) fn optimal_parse(&mut self) -> Vec<Option<MatchResult>> {
+        let start = self.work_pos;
+        let count = self.input_end.saturating_sub(start);
+        let data_end = self.data_end();
+
+        // Cheapest known cost to reach each position, and the step that got
+        // there together with the position it started from. A repetition that
+        // runs past the end of the block ends at `count`.
+        let mut cost = vec![u32::MAX; count + 1];
+        let mut step = vec![(0, None); count + 1];
+        cost[0] = 0;
+
+        // Positions inside a long repetition are not searched again
+        let mut skip_until = 0;
+
+        for offset in 0..count {
+            if offset < skip_until {
+                continue;
+            }
+
+            let pos = start + offset;
+            let base = cost[offset];
+
+            let literal = base + self.literal_cost(pos);
+            if literal < cost[offset + 1] {
+                cost[offset + 1] = literal;
+                step[offset + 1] = (offset, None);
+            }
+
+            let found = self.find_repetition(pos);
+            let max_length = found.length.min(data_end - pos);
+
+            // Long repetitions are hardly ever worth splitting, so they are
+            // taken as a whole and parsing continues after them
+            let min_length = if max_length >= NICE_LENGTH {
+                skip_until = offset + max_length;
+                max_length
+            } else {
+                2
+            };
+
+            for length in min_length..=max_length {
+                // 2-byte repetitions can only be this far back
+                if length == 2 && found.distance > 0x100 {
+                    continue;
+                }
+
+                // Of the repetitions past the end of the block, only the
+                // longest one is worth considering
+                let end = offset + length;
+                if end > count && length != max_length {
+                    continue;
+                }
+
+                let total = base + self.repetition_cost(length, found.distance);
+                let target = end.min(count);
+                if total < cost[target] {
+                    cost[target] = total;
+                    step[target] = (offset, Some(MatchResult::new(length, found.distance)));
+                }
+            }
+        }
+
+        // Walk back from the end of the block to collect the chosen steps
+        let mut steps = Vec::new();
+        let mut offset = count;
+        while offset > 0 {
+            let (from, chosen) = step[offset];
+            steps.push(chosen);
+            offset = from;
+        }
+        steps.reverse();
+        steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompressionMode, DictionarySize};
+
+    /// Total number of bits needed for a sequence of steps from `start`
+    fn total_cost(state: &ImplodeState, start: usize, steps: &[Option<MatchResult>]) -> u32 {
+        let mut pos = start;
+        let mut bits = 0;
+        for chosen in steps {
+            match chosen {
+                Some(found) => {
+                    bits += state.repetition_cost(found.length, found.distance);
+                    pos += found.length;
+                }
+                None => {
+                    bits += state.literal_cost(pos);
+                    pos += 1;
+                }
+            }
+        }
+        bits
+    }
+
+    fn prepare(mode: CompressionMode, data: &[u8]) -> (ImplodeState, usize) {
+        let mut state = ImplodeState::new(mode, DictionarySize::Size2K).unwrap();
+        let start = state.work_pos;
+        state.load_input(data);
+        state.begin_block(true);
+        (state, start)
+    }
+
+    #[test]
+    fn test_parse_covers_block() {
+        let data = b"abcabcabcd abcabcabcd abcabc xyz".repeat(20);
+        let (mut state, start) = prepare(CompressionMode::Binary, &data);
+
+        let steps = state.optimal_parse();
+        let covered: usize = steps.iter().map(|s| s.map_or(1, |m| m.length)).sum();
+        assert_eq!(covered, data.len());
+        assert!(steps
+            .iter()
+            .flatten()
+            .all(|m| (2..=516).contains(&m.length)));
+        assert!(steps.iter().flatten().all(|m| m.distance <= 2048));
+        assert_eq!(state.work_pos, start);
+    }
+
+    #[test]
+    fn test_parse_not_worse_than_greedy() {
+        let data = b"the quick brown fox jumps over the lazy dog, the quick dog".repeat(8);
+
+        for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
+            let (mut state, start) = prepare(mode, &data);
+            let optimal = state.optimal_parse();
+            let optimal_bits = total_cost(&state, start, &optimal);
+
+            // PKLib's choices for the same block
+            let (mut state, start) = prepare(mode, &data);
+            let mut greedy = Vec::new();
+            let mut next_match = None;
+            while state.work_pos < state.input_end {
+                let chosen = state.select_repetition(state.work_pos, true, &mut next_match);
+                state.work_pos += chosen.map_or(1, |m| m.length);
+                greedy.push(chosen);
+            }
+            let greedy_bits = total_cost(&state, start, &greedy);
+
+            assert!(
+                optimal_bits <= greedy_bits,
+                "{mode:?}: optimal {optimal_bits} bits, greedy {greedy_bits} bits"
+            );
+        }
+    }
+}
@@ -4,7 +4,11 @@
 //! to find repetitions in the input data. It ports the FindRep function
 //! from the original PKLib implementation.
 
-use super::{byte_pair_hash, state::ImplodeState, MAX_REP_LENGTH};
+use super::{state::ImplodeState, MAX_REP_LENGTH};
+use crate::CompressionLevel;
+
+/// Number of repetition candidates examined at `CompressionLevel::Fast`
+const FAST_MAX_CANDIDATES: usize = 32;
 
 /// Result of pattern matching
 #[derive(Debug, Clone, Copy)]
@@ -36,186 +40,283 @@ impl MatchResult {
 }
 
 impl ImplodeState {
+    /// Read a byte of the work buffer the way PKLib's FindRep sees it
+    ///
+    /// Repetition searches near the end of the input compare up to
+    /// `MAX_REP_LENGTH` bytes past the valid data. In PKLib's TCmpStruct the
+    /// work buffer is immediately followed by `phash_offs`, so reads beyond its
+    /// end return the (little-endian) bytes of that table.
+    pub(crate) fn window_byte(&self, pos: usize) -> u8 {
+        match self.work_buff.get(pos) {
+            Some(&byte) => byte,
+            None => {
+                let offset = pos - self.work_buff.len();
+                (self.phash_offs[offset / 2] >> ((offset % 2) * 8)) as u8
+            }
+        }
+    }
+
     /// Find the longest repetition at the current position
     /// This is a port of the FindRep function from PKLib implode.c
+    ///
+    /// `input_pos` is an absolute position in the work buffer. The returned
+    /// repetition may extend past the end of the input data; the caller has to
+    /// shorten it and reject 2-byte repetitions that are too far back, exactly
+    /// like PKLib's WriteCmpData does.
     pub fn find_repetition(&mut self, input_pos: usize) -> MatchResult {
-        // Need at least 2 bytes for a pattern
-        if input_pos + 1 >= self.work_bytes {
-            return MatchResult::no_match();
+        match self.find_rep(input_pos) {
+            0 => MatchResult::no_match(),
+            length => MatchResult::new(length, self.distance as usize + 1),
         }
+    }
 
-        let hash = byte_pair_hash(&self.work_buff[input_pos..input_pos + 2]);
-        let min_offset = input_pos.saturating_sub(self.dsize_bytes as usize);
-
-        // Get all positions where this byte pair hash occurs
-        let positions = self.find_hash_positions(hash, input_pos);
-        if positions.is_empty() {
-            return MatchResult::no_match();
+    /// Decide whether to write a repetition or a literal at `input_pos`
+    ///
+    /// This follows the main loop of PKLib's WriteCmpData, including its
+    /// one-byte lazy evaluation: when the repetition at the next position is
+    /// better, a literal is written and that repetition is left in
+    /// `next_match` for the next call.
+    pub(crate) fn select_repetition(
+        &mut self,
+        input_pos: usize,
+        input_ended: bool,
+        next_match: &mut Option<MatchResult>,
+    ) -> Option<MatchResult> {
+        let mut match_result = match next_match.take() {
+            Some(found) => found,
+            None => self.find_repetition(input_pos),
+        };
+
+        if !match_result.is_match() {
+            return None;
         }
 
-        let mut best_match = MatchResult::no_match();
-        let max_length = (self.work_bytes - input_pos).min(MAX_REP_LENGTH);
-
-        // Check each potential match position
-        for &match_pos in &positions {
-            // Skip if the position is too close or beyond our dictionary window
-            if match_pos >= input_pos || match_pos < min_offset {
-                continue;
-            }
-
-            // Calculate distance
-            let distance = input_pos - match_pos;
-
-            // For very close repetitions (distance 1), we need special handling
-            if distance == 1 {
-                // This is a run-length pattern (e.g., "aaaa...")
-                let match_length = self.find_run_length_match(input_pos, max_length);
-                if match_length > best_match.length {
-                    best_match = MatchResult::new(match_length, distance);
-                }
-                continue;
-            }
-
-            // Find the length of this match
-            let match_length = self.compare_sequences(input_pos, match_pos, max_length);
-
-            // Update best match if this one is better
-            // PKLib prefers the most recent match when lengths are equal (smaller distance)
-            if match_length > best_match.length
-                || (match_length == best_match.length && distance < best_match.distance)
-            {
-                best_match = MatchResult::new(match_length, distance);
+        // If we found a repetition that is 2 bytes long, and the distance
+        // is too big, we rather write it as literal
+        if match_result.length == 2 && match_result.distance > 0x100 {
+            return None;
+        }
 
-                // If we found a very long match, we can stop searching
-                if match_length >= MAX_REP_LENGTH {
-                    break;
-                }
-            }
+        // If we are at the end of the input data, we cannot have a
+        // repetition that goes past the end
+        if input_ended && input_pos + match_result.length > self.input_end {
+            match_result.length = self.input_end - input_pos;
+            let usable = match_result.length > 2
+                || (match_result.length == 2 && match_result.distance <= 0x100);
+            return usable.then_some(match_result);
         }
 
-        // PKLib validation: for 2-byte repetitions with distance >= 0x100, don't use them
-        // because storing the distance would take more space than the literal bytes
-        if best_match.is_match() && best_match.length == 2 && best_match.distance >= 0x100 {
-            return MatchResult::no_match();
+        // Repetitions of 8 bytes or more are written immediately, as is
+        // the repetition at the last input byte. The fast level never looks
+        // ahead.
+        if match_result.length >= 8
+            || input_pos + 1 >= self.input_end
+            || self.level == CompressionLevel::Fast
+        {
+            return Some(match_result);
         }
 
-        // Store the distance in our state for encoding
-        if best_match.is_match() {
-            self.distance = (best_match.distance - 1) as u32; // PKLib stores distance - 1
+        // Check whether the next position offers a better repetition. Only use
+        // it if it is longer by 2 bytes, or longer by 1 byte and the current
+        // one is far away.
+        let next = self.find_repetition(input_pos + 1);
+        if next.length > match_result.length
+            && (next.length > match_result.length + 1 || match_result.distance > 0x81)
+        {
+            *next_match = Some(next);
+            return None;
         }
 
-        best_match
+        // The search at the next position changed the stored distance
+        self.distance = (match_result.distance - 1) as u32;
+        Some(match_result)
     }
 
-    /// Compare two sequences and return the length of the match
-    fn compare_sequences(&self, pos1: usize, pos2: usize, max_length: usize) -> usize {
-        // First check if the sequences start with the same byte pair
-        if pos1 + 1 >= self.work_buff.len() || pos2 + 1 >= self.work_buff.len() {
-            return 0;
+    /// Port of FindRep, returning the repetition length and leaving the
+    /// backward distance (decreased by 1) in `self.distance`
+    fn find_rep(&mut self, input: usize) -> usize {
+        // Calculate the previous position of the PAIR_HASH
+        let hash = self.pair_hash_at(input);
+        let min_phash_offs = input + 1 - self.dsize_bytes as usize;
+        let mut index = self.phash_to_index[hash] as usize;
+
+        // If the PAIR_HASH offset is below the limit, find a next one
+        if (self.phash_offs[index] as usize) < min_phash_offs {
+            while (self.phash_offs[index] as usize) < min_phash_offs {
+                index += 1;
+            }
+            self.phash_to_index[hash] = index as u16;
         }
 
-        if self.work_buff[pos1] != self.work_buff[pos2]
-            || self.work_buff[pos1 + 1] != self.work_buff[pos2 + 1]
-        {
+        // Get the first location of the PAIR_HASH, and thus the first eventual
+        // location of byte repetition. A repetition must start below the limit.
+        let mut prev = self.phash_offs[index] as usize;
+        let limit = input - 1;
+        if prev >= limit {
             return 0;
         }
 
-        // Start with 2 bytes already matched
-        let mut length = 2;
+        // PAIR_HASH is not unique, so compare the bytes and count the length
+        // of the repetition
+        let fast = self.level == CompressionLevel::Fast;
+        let mut candidates = 0;
+        let mut rep_length = 1;
+        loop {
+            if self.window_byte(input) == self.window_byte(prev)
+                && self.window_byte(input + rep_length - 1)
+                    == self.window_byte(prev + rep_length - 1)
+            {
+                // The second byte is equal too, because the hashes are equal
+                let mut equal_byte_count = 2;
+                while equal_byte_count < MAX_REP_LENGTH {
+                    if self.window_byte(prev + equal_byte_count)
+                        != self.window_byte(input + equal_byte_count)
+                    {
+                        break;
+                    }
+                    equal_byte_count += 1;
+                }
 
-        // Compare remaining bytes
-        while length < max_length {
-            let idx1 = pos1 + length;
-            let idx2 = pos2 + length;
+                // Take repetitions of at least the same length, so that the most
+                // recent one wins and the distance can be stored in fewer bits
+                if equal_byte_count >= rep_length {
+                    self.distance = (input - prev - 1) as u32;
+                    rep_length = equal_byte_count;
 
-            if idx1 >= self.work_buff.len() || idx2 >= self.work_buff.len() {
-                break;
+                    // Repetitions longer than 10 bytes are handled below
+                    if rep_length > 10 {
+                        break;
+                    }
+                }
             }
 
-            if self.work_buff[idx1] != self.work_buff[idx2] {
-                break;
+            // Move forward in the table of PAIR_HASH repetitions
+            index += 1;
+            prev = self.phash_offs[index] as usize;
+            candidates += 1;
+            if prev >= limit || (fast && candidates >= FAST_MAX_CANDIDATES) {
+                return if rep_length >= 2 { rep_length } else { 0 };
             }
-
-            length += 1;
         }
 
-        length
-    }
-
-    /// Handle run-length encoding (repeating single character)
-    fn find_run_length_match(&self, pos: usize, max_length: usize) -> usize {
-        if pos >= self.work_buff.len() {
-            return 0;
+        // A repetition of maximum length can't be improved upon, and the fast
+        // level settles for the first long one
+        if rep_length == MAX_REP_LENGTH || fast {
+            return rep_length;
         }
 
-        let byte_value = self.work_buff[pos];
-        let mut length = 1;
-
-        // Count how many consecutive bytes match
-        while length < max_length && pos + length < self.work_buff.len() {
-            if self.work_buff[pos + length] != byte_value {
-                break;
-            }
-            length += 1;
+        // Check for possibility of a repetition that occurs at more recent position
+        if self.phash_offs[index + 1] as usize >= limit {
+            return rep_length;
         }
 
-        length
-    }
-
-    /// Find matches for optimization (PKLib's advanced pattern matching)
-    /// This implements the complex optimization logic from the original FindRep
-    pub fn find_optimized_match(
-        &mut self,
-        input_pos: usize,
-        current_best: MatchResult,
-    ) -> MatchResult {
-        // This is a simplified version of PKLib's optimization logic
-        // The full version is quite complex and handles many edge cases
-
-        if !current_best.is_match() || current_best.length < 10 {
-            return current_best;
-        }
-
-        // Look for potentially better matches at different positions
-        let hash = byte_pair_hash(&self.work_buff[input_pos..input_pos + 2]);
-        let positions = self.find_hash_positions(hash, input_pos);
+        // Check whether a later occurrence yields a longer repetition, e.g. for
+        // "EEEE...EEEEXYZ" followed by "EEEE...EEEEXYZ" and "XYZ". The
+        // offs_buffer table (offs09BC in PKLib) holds, for each prefix length of
+        // the repetition, the length of its longest proper border (like the
+        // KMP failure function), so the search can skip ahead without
+        // comparing bytes that are known to match.
+        let mut di_val = 0u16;
+        let mut offs_in_rep = 1;
+        self.offs_buffer[0] = 0xFFFF;
+        self.offs_buffer[1] = 0x0000;
+        self.fill_offs_buffer(input, &mut offs_in_rep, &mut di_val, rep_length);
+
+        // Go through the repetitions following the found one
+        prev = self.phash_offs[index] as usize;
+        let mut prev_rep_end = prev + rep_length;
+        let mut rep_length2 = rep_length;
+
+        loop {
+            rep_length2 = match self.offs_buffer[rep_length2] {
+                0xFFFF => 0,
+                border => border as usize,
+            };
+
+            // Skip those repetitions that don't reach the end of the first found one
+            loop {
+                index += 1;
+                prev = self.phash_offs[index] as usize;
+                if prev >= limit {
+                    return rep_length;
+                }
+                if prev + rep_length2 >= prev_rep_end {
+                    break;
+                }
+            }
 
-        let mut best_match = current_best;
-        let max_length = (self.work_bytes - input_pos).min(MAX_REP_LENGTH);
+            // Verify that the last but one byte of the repetition matches the
+            // last but one byte of the input data
+            let pre_last_byte = self.window_byte(input + rep_length - 2);
+            if pre_last_byte == self.window_byte(prev + rep_length - 2) {
+                // If the new repetition reaches beyond the end of the previously
+                // found one, count it from the start
+                if prev + rep_length2 != prev_rep_end {
+                    prev_rep_end = prev;
+                    rep_length2 = 0;
+                }
+            } else {
+                loop {
+                    index += 1;
+                    prev = self.phash_offs[index] as usize;
+                    if prev >= limit {
+                        return rep_length;
+                    }
+                    if self.window_byte(prev + rep_length - 2) == pre_last_byte
+                        && self.window_byte(prev) == self.window_byte(input)
+                    {
+                        break;
+                    }
+                }
 
-        // Check if there's a longer match at a different position
-        for &match_pos in &positions {
-            if match_pos >= input_pos {
-                continue;
+                // Reset the length of the repetition to 2 bytes only
+                prev_rep_end = prev + 2;
+                rep_length2 = 2;
             }
 
-            let distance = input_pos - match_pos;
-            if distance == best_match.distance {
-                continue; // Same match we already found
+            // Find out how many more bytes are equal
+            while self.window_byte(prev_rep_end) == self.window_byte(input + rep_length2) {
+                rep_length2 += 1;
+                if rep_length2 >= MAX_REP_LENGTH {
+                    break;
+                }
+                prev_rep_end += 1;
             }
 
-            let match_length = self.compare_sequences(input_pos, match_pos, max_length);
+            // Is the newly found repetition at least as long as the previous one?
+            if rep_length2 >= rep_length {
+                self.distance = (input - prev - 1) as u32;
+                rep_length = rep_length2;
+                if rep_length == MAX_REP_LENGTH {
+                    return rep_length;
+                }
 
-            // Prefer longer matches, or closer matches of the same length
-            if match_length > best_match.length {
-                best_match = MatchResult::new(match_length, distance);
-                self.distance = (distance - 1) as u32;
+                // Extend the offs_buffer table to the new repetition length
+                self.fill_offs_buffer(input, &mut offs_in_rep, &mut di_val, rep_length);
             }
         }
-
-        best_match
     }
 
-    /// Quick check if a position might have a good match
-    /// Used for performance optimization in the inner loop
-    pub fn quick_match_check(&self, pos1: usize, pos2: usize, min_length: usize) -> bool {
-        if pos1 + min_length > self.work_buff.len() || pos2 + min_length > self.work_buff.len() {
-            return false;
+    /// Extend the border table in `offs_buffer` up to `rep_length` bytes
+    fn fill_offs_buffer(
+        &mut self,
+        input: usize,
+        offs_in_rep: &mut usize,
+        di_val: &mut u16,
+        rep_length: usize,
+    ) {
+        while *offs_in_rep < rep_length {
+            if self.window_byte(input + *offs_in_rep) != self.window_byte(input + *di_val as usize)
+            {
+                *di_val = self.offs_buffer[*di_val as usize];
+                if *di_val != 0xFFFF {
+                    continue;
+                }
+            }
+            *di_val = di_val.wrapping_add(1);
+            *offs_in_rep += 1;
+            self.offs_buffer[*offs_in_rep] = *di_val;
         }
-
-        // Check first and last bytes of minimum required length
-        self.work_buff[pos1] == self.work_buff[pos2]
-            && self.work_buff[pos1 + min_length - 1] == self.work_buff[pos2 + min_length - 1]
     }
 }
 
@@ -230,71 +331,147 @@ mod tests {
 
         // Set up unique data with no repetitions
         let test_data = b"ABCDEFGHIJ";
-        let len = test_data.len().min(state.work_buff.len());
-        state.work_buff[..len].copy_from_slice(&test_data[..len]);
-        state.work_bytes = len;
+        let start = state.work_pos;
+        state.load_input(test_data);
 
         // Build hash table
-        state.sort_buffer(0, len);
+        state.begin_block(true);
 
         // Look for match at position that has no previous occurrence
-        let result = state.find_repetition(5);
+        let result = state.find_repetition(start + 5);
         assert!(!result.is_match());
     }
 
     #[test]
-    #[ignore] // TODO: Fix after compression refactoring
     fn test_simple_match() {
         let mut state = ImplodeState::new(CompressionMode::Binary, DictionarySize::Size1K).unwrap();
 
         // Set up data with a clear repetition
         let test_data = b"ABCDEFABCDEF";
-        let len = test_data.len().min(state.work_buff.len());
-        state.work_buff[..len].copy_from_slice(&test_data[..len]);
-        state.work_bytes = len;
+        let start = state.work_pos;
+        state.load_input(test_data);
 
         // Build hash table
-        state.sort_buffer(0, len);
+        state.begin_block(true);
 
         // Look for match at position 6 (second "AB")
-        let result = state.find_repetition(6);
+        let result = state.find_repetition(start + 6);
         assert!(result.is_match());
         assert_eq!(result.distance, 6); // Distance from position 6 to position 0
-        assert!(result.length >= 2); // At least "AB" should match
+        assert_eq!(result.length, 6); // The whole "ABCDEF" repeats
+    }
+
+    /// Load data as the final block and build its hash table
+    fn prepare(state: &mut ImplodeState, data: &[u8]) -> usize {
+        let start = state.work_pos;
+        state.load_input(data);
+        state.begin_block(true);
+        start
     }
 
     #[test]
-    fn test_run_length_match() {
+    fn test_prefers_most_recent_match() {
         let mut state = ImplodeState::new(CompressionMode::Binary, DictionarySize::Size1K).unwrap();
+        let start = prepare(&mut state, b"ABCD....ABCD----ABCD");
 
-        // Set up data with run-length pattern
-        let test_data = b"AAAAAAAAA";
-        let len = test_data.len().min(state.work_buff.len());
-        state.work_buff[..len].copy_from_slice(&test_data[..len]);
-        state.work_bytes = len;
+        // Both earlier "ABCD" are equally long, the closer one must win
+        let result = state.find_repetition(start + 16);
+        assert_eq!(result.length, 4);
+        assert_eq!(result.distance, 8);
+    }
 
-        // Test run-length matching
-        let length = state.find_run_length_match(0, len);
-        assert_eq!(length, len);
+    #[test]
+    fn test_longer_match_at_later_offset() {
+        let mut state = ImplodeState::new(CompressionMode::Binary, DictionarySize::Size4K).unwrap();
+
+        // The first occurrence of the long run is followed by different data,
+        // only the second one continues with "XYZ"
+        let mut data = vec![b'E'; 40];
+        data.extend_from_slice(b"QQ");
+        data.extend(std::iter::repeat_n(b'E', 40));
+        data.extend_from_slice(b"XYZ");
+        let second = data.len();
+        data.extend(std::iter::repeat_n(b'E', 40));
+        data.extend_from_slice(b"XYZ");
+        let start = prepare(&mut state, &data);
+
+        let result = state.find_repetition(start + second);
+        assert_eq!(result.length, 43);
+        assert_eq!(result.distance, 43);
+    }
+
+    #[test]
+    fn test_max_length_match() {
+        let mut state = ImplodeState::new(CompressionMode::Binary, DictionarySize::Size2K).unwrap();
+        let data: Vec<u8> = (0..1200u32).map(|i| (i % 600) as u8).collect();
+        let start = prepare(&mut state, &data);
+
+        let result = state.find_repetition(start + 600);
+        assert_eq!(result.length, MAX_REP_LENGTH);
+        assert_eq!(result.distance, 600);
     }
 
     #[test]
-    fn test_compare_sequences() {
+    fn test_match_outside_dictionary() {
         let mut state = ImplodeState::new(CompressionMode::Binary, DictionarySize::Size1K).unwrap();
+        let mut data = b"XYZW".to_vec();
+        data.extend((0..1100u32).map(|i| (i % 200) as u8 | 0x80));
+        data.extend_from_slice(b"XYZW");
+        let start = prepare(&mut state, &data);
 
-        // Set up test data
-        let test_data = b"ABCDEFABCXYZ";
-        let len = test_data.len().min(state.work_buff.len());
-        state.work_buff[..len].copy_from_slice(&test_data[..len]);
-        state.work_bytes = len;
+        // The only earlier "XYZW" is more than 1 KB back
+        let result = state.find_repetition(start + data.len() - 4);
+        assert!(!result.is_match());
+    }
 
-        // Compare "ABC" at position 0 with "ABC" at position 6
-        let length = state.compare_sequences(0, 6, 6);
-        assert_eq!(length, 3); // "ABC" matches
+    #[test]
+    fn test_fast_level_limits_candidates() {
+        // 40 two-byte repetitions of "AB", followed by the only long one
+        let mut data = Vec::new();
+        for i in 0..40u8 {
+            data.extend_from_slice(&[b'A', b'B', 0x80 + i]);
+        }
+        data.extend_from_slice(b"ABCDEF#ABCDEF");
+        let input = data.len() - 6;
 
-        // Compare sequences that don't match
-        let length = state.compare_sequences(0, 3, 3);
-        assert_eq!(length, 0); // "ABC" vs "DEF" - no match
+        let mut state = ImplodeState::new(CompressionMode::Binary, DictionarySize::Size1K).unwrap();
+        let start = prepare(&mut state, &data);
+        let result = state.find_repetition(start + input);
+        assert_eq!(result.length, 6);
+        assert_eq!(result.distance, 7);
+
+        // The fast level gives up after the oldest FAST_MAX_CANDIDATES ones
+        let mut state = ImplodeState::new(CompressionMode::Binary, DictionarySize::Size1K).unwrap();
+        state.level = CompressionLevel::Fast;
+        let start = prepare(&mut state, &data);
+        let result = state.find_repetition(start + input);
+        assert_eq!(result.length, 2);
+        assert_eq!(result.distance, input - 3 * (FAST_MAX_CANDIDATES - 1));
+    }
+
+    #[test]
+    fn test_fast_level_skips_lazy_matching() {
+        // "XABC" repeats 4 bytes, the next position repeats "ABCDEFG"
+        let data = b"XABC-ABCDEFG-XABCDEFG!";
+        let input = 13;
+
+        let mut state = ImplodeState::new(CompressionMode::Binary, DictionarySize::Size1K).unwrap();
+        let start = prepare(&mut state, data);
+        let mut next_match = None;
+        let selected = state.select_repetition(start + input, true, &mut next_match);
+        assert!(selected.is_none());
+        assert_eq!(next_match.map(|found| found.length), Some(7));
+
+        let mut state = ImplodeState::new(CompressionMode::Binary, DictionarySize::Size1K).unwrap();
+        state.level = CompressionLevel::Fast;
+        let start = prepare(&mut state, data);
+        let mut next_match = None;
+        let selected = state.select_repetition(start + input, true, &mut next_match);
+        assert_eq!(
+            selected.map(|found| (found.length, found.distance)),
+            Some((4, 13))
+        );
+        assert!(next_match.is_none());
     }
 
     #[test]
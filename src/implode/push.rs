@@ -0,0 +1,221 @@
+//! ImplodeEncoder - Push-style (sans-IO) compression
+//!
+//! This module implements a compressor that writes into caller-provided
+//! output slices instead of a `Write` sink. The compressed bytes of each input
+//! block are staged in a fixed-size buffer and handed out as output space
+//! becomes available, so no memory is allocated while compressing at the
+//! `Fast` and `Default` levels.
+
+use super::state::ImplodeState;
+use crate::{
+    CompressionLevel, CompressionMode, CompressionStats, DictionarySize, PkLibError, Result, Status,
+};
+
+/// Capacity for the compressed bytes of one input block
+///
+/// A block covers at most 0x1204 input bytes (a repetition may reach into the
+/// lookahead), each taking at most 14 bits, plus up to 0x800 bytes still held
+/// in the bit output buffer and the end marker.
+const PENDING_SIZE: usize = 0x2800;
+
+/// Push-style compressor writing into caller-provided output slices
+///
+/// Pass input to `compress()` in pieces of any size, then call `finish()`
+/// until it reports `Status::StreamEnd`. Both hand out as much compressed
+/// data as fits into `output` and report how to continue:
+///
+/// - `Status::Ok`: the output buffer is full, call again with more space
+/// - `Status::GetInput`: all input was consumed, call again with more input
+///   (or `finish()` once there is none left)
+/// - `Status::StreamEnd`: the stream is complete and all output delivered
+///
+/// The compressed stream is the same as `ImplodeWriter` produces for the
+/// same settings and input.
+#[derive(Debug)]
+pub struct ImplodeEncoder {
+    state: ImplodeState,
+    initialized: bool,
+    finishing: bool,
+    finished: bool,
+    /// Compressed data waiting to be handed out
+    pending: [u8; PENDING_SIZE],
+    pending_start: usize,
+    pending_end: usize,
+}
+
+impl ImplodeEncoder {
+    /// Create a new ImplodeEncoder
+    pub fn new(mode: CompressionMode, dict_size: DictionarySize) -> Result<Self> {
+        Self::with_level(mode, dict_size, CompressionLevel::Default)
+    }
+
+    /// Create a new ImplodeEncoder using the given compression level
+    ///
+    /// At `CompressionLevel::Best`, the optimal parser allocates working
+    /// memory for every block.
+    pub fn with_level(
+        mode: CompressionMode,
+        dict_size: DictionarySize,
+        level: CompressionLevel,
+    ) -> Result<Self> {
+        let mut state = ImplodeState::new(mode, dict_size)?;
+        state.level = level;
+        Ok(Self {
+            state,
+            initialized: false,
+            finishing: false,
+            finished: false,
+            pending: [0; PENDING_SIZE],
+            pending_start: 0,
+            pending_end: 0,
+        })
+    }
+
+    /// Use a preset dictionary for compression
+    ///
+    /// See `ImplodeWriter::with_dictionary`. The dictionary has to be set
+    /// before the first call to `compress()`.
+    pub fn with_dictionary(mut self, dictionary: &[u8]) -> Result<Self> {
+        if self.state.input_pos != 0 || self.finishing {
+            return Err(PkLibError::InvalidData(
+                "Dictionary must be set before compressing".to_string(),
+            ));
+        }
+
+        self.state.set_dictionary(dictionary);
+        Ok(self)
+    }
+
+    /// Compress from `input` into `output`
+    ///
+    /// Returns the number of input bytes consumed, the number of output bytes
+    /// produced, and the status telling how to continue. Unconsumed input has
+    /// to be passed again on the next call.
+    pub fn compress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(usize, usize, Status)> {
+        if self.finishing {
+            return Err(PkLibError::InvalidData(
+                "Cannot compress after finish".to_string(),
+            ));
+        }
+
+        let mut consumed = 0;
+        let mut produced = 0;
+
+        loop {
+            produced += self.drain(&mut output[produced..]);
+            if self.pending_start < self.pending_end {
+                return Ok((consumed, produced, Status::Ok));
+            }
+
+            // The work buffer is full, compress it before loading more input
+            if self.state.block_full() {
+                self.compress_block(false)?;
+                continue;
+            }
+
+            if consumed == input.len() {
+                return Ok((consumed, produced, Status::GetInput));
+            }
+            consumed += self.state.load_input(&input[consumed..]);
+        }
+    }
+
+    /// Compress the remaining input and write the end of the stream into
+    /// `output`
+    ///
+    /// Returns the number of output bytes produced and `Status::StreamEnd` once
+    /// all compressed data was handed out, or `Status::Ok` if `finish()` has to
+    /// be called again with more output space.
+    pub fn finish(&mut self, output: &mut [u8]) -> Result<(usize, Status)> {
+        self.finishing = true;
+
+        let mut produced = 0;
+        loop {
+            produced += self.drain(&mut output[produced..]);
+            if self.pending_start < self.pending_end {
+                return Ok((produced, Status::Ok));
+            }
+            if self.finished {
+                return Ok((produced, Status::StreamEnd));
+            }
+
+            // A full block left over from compress() goes first
+            if self.state.block_full() {
+                self.compress_block(false)?;
+                continue;
+            }
+
+            self.finish_output()?;
+            self.finished = true;
+        }
+    }
+
+    /// Statistics of the data compressed so far
+    ///
+    /// See `ImplodeWriter::stats`; `output_bytes` counts the compressed bytes
+    /// of every block compressed so far, including those still waiting to be
+    /// handed out.
+    pub fn stats(&self) -> CompressionStats {
+        self.state.stats()
+    }
+
+    /// Whether the whole stream has been written out
+    pub fn is_finished(&self) -> bool {
+        self.finished && self.pending_start == self.pending_end
+    }
+
+    /// Copy pending compressed data into `output`
+    fn drain(&mut self, output: &mut [u8]) -> usize {
+        let count = (self.pending_end - self.pending_start).min(output.len());
+        output[..count]
+            .copy_from_slice(&self.pending[self.pending_start..self.pending_start + count]);
+        self.pending_start += count;
+        count
+    }
+
+    /// Sink appending compressed bytes to the (drained) pending buffer
+    fn stage(pending: &mut [u8], pending_end: &mut usize, bytes: &[u8]) -> Result<()> {
+        let end = *pending_end + bytes.len();
+        pending
+            .get_mut(*pending_end..end)
+            .ok_or(PkLibError::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        *pending_end = end;
+        Ok(())
+    }
+
+    /// Compress the current input block into the pending buffer
+    fn compress_block(&mut self, input_ended: bool) -> Result<()> {
+        if !self.initialized {
+            self.state.begin_output();
+            self.initialized = true;
+        }
+
+        self.pending_start = 0;
+        self.pending_end = 0;
+        let (pending, pending_end) = (&mut self.pending, &mut self.pending_end);
+        self.state.compress_block(input_ended, &mut |bytes| {
+            Self::stage(pending, pending_end, bytes)
+        })
+    }
+
+    /// Compress the final block and stage the end marker and remaining output
+    fn finish_output(&mut self) -> Result<()> {
+        // No block was compressed yet, so the header still has to be set up
+        if !self.initialized {
+            self.state.begin_output();
+            self.initialized = true;
+        }
+
+        self.pending_start = 0;
+        self.pending_end = 0;
+        let (pending, pending_end) = (&mut self.pending, &mut self.pending_end);
+        let mut sink = |bytes: &[u8]| Self::stage(pending, pending_end, bytes);
+
+        if self.state.has_pending_input() {
+            self.state.compress_block(true, &mut sink)?;
+        }
+        self.state.write_end_marker(&mut sink)?;
+        self.state.flush_output(&mut sink)
+    }
+}
@@ -3,11 +3,16 @@
 //! This module manages the internal state for PKLib implode compression,
 //! matching the TCmpStruct from the original PKLib implementation.
 
-use super::{HASH_TABLE_SIZE, LITERALS_COUNT, OFFSS_SIZE2, OUT_BUFF_SIZE, WORK_BUFF_SIZE};
+use super::{
+    HASH_TABLE_SIZE, INPUT_BLOCK_SIZE, LITERALS_COUNT, MAX_REP_LENGTH, OFFSS_SIZE2, OUT_BUFF_SIZE,
+    WORK_BUFF_SIZE,
+};
 use crate::tables::{
     CH_BITS_ASC, CH_CODE_ASC, DIST_BITS, DIST_CODE, EX_LEN_BITS, LEN_BITS, LEN_CODE,
 };
-use crate::{CompressionMode, DictionarySize, Result};
+use crate::{
+    crc32_pklib, CompressionLevel, CompressionMode, CompressionStats, DictionarySize, Result,
+};
 
 /// Compression state structure matching PKLib's TCmpStruct
 #[derive(Debug)]
@@ -50,12 +55,25 @@ pub struct ImplodeState {
     pub offs_buffer: [u16; OFFSS_SIZE2],
 
     // Internal state
-    /// Current position in work buffer
+    /// Current position in work buffer (PKLib's `input_data` pointer)
     pub work_pos: usize,
-    /// Current position in input data
+    /// Total number of input bytes accepted so far
     pub input_pos: usize,
-    /// Amount of data in work buffer
+    /// Number of bytes loaded into the current input block (0..=0x1000)
     pub work_bytes: usize,
+    /// End of the data to be compressed in the current block (`input_data_end`)
+    pub input_end: usize,
+    /// Which part of the work buffer needs sorting for the next block (0, 1 or 2)
+    pub phase: u32,
+    /// How thoroughly to search for repetitions
+    pub level: CompressionLevel,
+    /// Number of preset dictionary bytes in front of the first input block
+    pub preset_len: usize,
+    /// Number of compressed bytes, including the header, at which output
+    /// stops with `PkLibError::BufferTooSmall`
+    pub max_output: Option<u64>,
+    /// Statistics of the data compressed so far
+    pub stats: CompressionStats,
 }
 
 impl ImplodeState {
@@ -85,7 +103,14 @@ impl ImplodeState {
             work_pos: 0,
             input_pos: 0,
             work_bytes: 0,
+            input_end: 0,
+            phase: 0,
+            level: CompressionLevel::Default,
+            preset_len: 0,
+            max_output: None,
+            stats: CompressionStats::default(),
         };
+        state.work_pos = state.input_offset();
 
         // Copy static tables
         state.dist_bits.copy_from_slice(&DIST_BITS);
@@ -142,9 +167,13 @@ impl ImplodeState {
         self.distance = 0;
         self.out_bytes = 0;
         self.out_bits = 0;
-        self.work_pos = 0;
+        self.work_pos = self.input_offset();
         self.input_pos = 0;
         self.work_bytes = 0;
+        self.input_end = 0;
+        self.phase = 0;
+        self.preset_len = 0;
+        self.stats = CompressionStats::with_level(self.stats.level());
 
         // Clear buffers
         self.phash_to_index.fill(0);
@@ -154,29 +183,107 @@ impl ImplodeState {
         self.offs_buffer.fill(0);
     }
 
-    /// Get current compression statistics
-    pub fn stats(&self) -> CompressionStats {
-        CompressionStats {
-            bytes_processed: self.input_pos,
-            compressed_bytes: self.out_bytes as usize,
-            compression_ratio: if self.input_pos > 0 {
-                (self.out_bytes as f64) / (self.input_pos as f64)
-            } else {
-                0.0
-            },
+    /// Offset in the work buffer where new input blocks are loaded
+    ///
+    /// The area below holds the dictionary, followed by `MAX_REP_LENGTH` bytes
+    /// of lookahead carried over from the previous block.
+    pub fn input_offset(&self) -> usize {
+        self.dsize_bytes as usize + MAX_REP_LENGTH
+    }
+
+    /// Offset one past the last valid input byte in the work buffer
+    pub fn data_end(&self) -> usize {
+        self.input_offset() + self.work_bytes
+    }
+
+    /// Copy input data into the current block, returning the number of bytes taken
+    ///
+    /// At most `INPUT_BLOCK_SIZE` bytes are buffered; once the block is full it
+    /// has to be compressed and the window slid before more data is accepted.
+    pub fn load_input(&mut self, data: &[u8]) -> usize {
+        let start = self.data_end();
+        let count = data.len().min(INPUT_BLOCK_SIZE - self.work_bytes);
+        self.work_buff[start..start + count].copy_from_slice(&data[..count]);
+        self.work_bytes += count;
+        self.input_pos += count;
+
+        self.stats.input_bytes += count as u64;
+        self.stats.bytes_processed += count;
+        self.stats.crc32 = crc32_pklib(&data[..count], self.stats.crc32);
+        count
+    }
+
+    /// Check whether the current input block is full
+    pub fn block_full(&self) -> bool {
+        self.work_bytes == INPUT_BLOCK_SIZE
+    }
+
+    /// Check whether there is any data left to compress in the final block
+    ///
+    /// PKLib skips the final block entirely when no input was ever loaded.
+    pub fn has_pending_input(&self) -> bool {
+        self.work_bytes != 0 || self.phase != 0
+    }
+
+    /// Prepare the current block for compression (first half of PKLib's WriteCmpData loop)
+    ///
+    /// Unless this is the last block, the final `MAX_REP_LENGTH` bytes are held
+    /// back so that repetitions starting near the end can still be found in full.
+    /// The hash table is rebuilt over the dictionary and the new data.
+    pub fn begin_block(&mut self, input_ended: bool) {
+        let dsize = self.dsize_bytes as usize;
+
+        self.input_end = dsize + self.work_bytes;
+        if input_ended {
+            self.input_end += MAX_REP_LENGTH;
         }
+
+        // Search the PAIR_HASHes of the loaded block, including previously
+        // compressed data (or the preset dictionary) still within the window
+        let sort_begin = match self.phase {
+            0 => self.work_pos - self.preset_len,
+            1 => (self.work_pos + MAX_REP_LENGTH).saturating_sub(dsize + self.preset_len),
+            _ => self.work_pos - dsize,
+        };
+        self.sort_buffer(sort_begin, self.input_end + 1);
+
+        self.phase = match self.phase {
+            0 if dsize != 0x1000 => 2,
+            phase => (phase + 1).min(2),
+        };
     }
-}
 
-/// Compression statistics
-#[derive(Debug, Clone)]
-pub struct CompressionStats {
-    /// Total bytes processed from input
-    pub bytes_processed: usize,
-    /// Total compressed bytes produced
-    pub compressed_bytes: usize,
-    /// Compression ratio (compressed/original)
-    pub compression_ratio: f64,
+    /// Prime the sliding window with a preset dictionary
+    ///
+    /// Only the last `dsize_bytes` of the dictionary can be referenced. This
+    /// must be called before any input is loaded.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        let len = dictionary.len().min(self.dsize_bytes as usize);
+        let input_offset = self.input_offset();
+
+        self.work_buff[input_offset - len..input_offset]
+            .copy_from_slice(&dictionary[dictionary.len() - len..]);
+        self.preset_len = len;
+    }
+
+    /// Slide the window forward by one block after it has been compressed
+    ///
+    /// The last `dsize_bytes` of compressed data stay available as the dictionary,
+    /// together with the lookahead bytes that have not been compressed yet.
+    pub fn slide_window(&mut self) {
+        let keep = self.dsize_bytes as usize + MAX_REP_LENGTH;
+        self.work_buff
+            .copy_within(INPUT_BLOCK_SIZE..INPUT_BLOCK_SIZE + keep, 0);
+        self.work_pos -= INPUT_BLOCK_SIZE;
+        self.work_bytes = 0;
+    }
+
+    /// Get current compression statistics
+    pub fn stats(&self) -> CompressionStats {
+        let mut stats = self.stats.clone();
+        stats.compression_ratio = CompressionStats::ratio(stats.output_bytes, stats.input_bytes);
+        stats
+    }
 }
 
 impl Default for ImplodeState {
@@ -0,0 +1,112 @@
+//! TokenEncoder - Compression from a sequence of tokens
+//!
+//! This module writes literals and repetitions chosen by the caller instead
+//! of searching for repetitions itself, for custom match finders and for
+//! re-encoding token streams read with `TokenReader`.
+
+use super::{pattern::MatchResult, state::ImplodeState, MAX_REP_LENGTH};
+use crate::{CompressionMode, DictionarySize, PkLibError, Result, Token};
+use std::io::Write;
+
+/// Encoder writing a compressed stream from caller-chosen tokens
+///
+/// Each token is validated before it is written: repetitions must be 2 to
+/// 516 bytes long, and their distance must lie within the dictionary size
+/// (256 bytes for repetitions of 2 bytes) and within the data written so far.
+/// A stream written from the tokens `TokenReader` yields for a PKLib stream
+/// is byte-identical to it.
+#[derive(Debug)]
+pub struct TokenEncoder<W: Write> {
+    writer: W,
+    state: ImplodeState,
+    /// Number of bytes repetitions can refer to
+    history: u64,
+    finished: bool,
+}
+
+impl<W: Write> TokenEncoder<W> {
+    /// Create a new TokenEncoder
+    pub fn new(writer: W, mode: CompressionMode, dict_size: DictionarySize) -> Result<Self> {
+        let mut state = ImplodeState::new(mode, dict_size)?;
+        state.begin_output();
+        Ok(Self {
+            writer,
+            state,
+            history: 0,
+            finished: false,
+        })
+    }
+
+    /// Allow repetitions to refer to a preset dictionary
+    ///
+    /// The stream can then only be decompressed with the same dictionary (see
+    /// `ExplodeReader::with_dictionary`). It has to be set before the first
+    /// token is written.
+    pub fn with_dictionary(mut self, dictionary: &[u8]) -> Result<Self> {
+        if self.history != 0 || self.finished {
+            return Err(PkLibError::InvalidData(
+                "Dictionary must be set before writing tokens".to_string(),
+            ));
+        }
+
+        self.history = dictionary.len() as u64;
+        Ok(self)
+    }
+
+    /// Encode one token
+    ///
+    /// Writing `Token::End` completes the stream; no tokens can follow it.
+    pub fn write_token(&mut self, token: Token) -> Result<()> {
+        if self.finished {
+            return Err(PkLibError::InvalidData(
+                "Cannot write tokens after the end of the stream".to_string(),
+            ));
+        }
+
+        let writer = &mut self.writer;
+        let mut sink = |bytes: &[u8]| Ok(writer.write_all(bytes)?);
+
+        match token {
+            Token::Literal(byte) => {
+                self.state.encode_literal(byte, &mut sink)?;
+                self.history += 1;
+            }
+            Token::Match { length, distance } => {
+                if !(2..=MAX_REP_LENGTH).contains(&(length as usize)) {
+                    return Err(PkLibError::InvalidLength(length as u32));
+                }
+
+                // Repetitions of 2 bytes only have 8 bits of distance
+                let max_distance = if length == 2 {
+                    0x100
+                } else {
+                    self.state.dsize_bytes
+                };
+                if distance == 0 || distance as u32 > max_distance || distance as u64 > self.history
+                {
+                    return Err(PkLibError::InvalidDistance(distance as u32));
+                }
+
+                let repetition = MatchResult::new(length as usize, distance as usize);
+                self.state.encode_match(repetition, &mut sink)?;
+                self.history += length as u64;
+            }
+            Token::End => {
+                self.state.write_end_marker(&mut sink)?;
+                self.state.flush_output(&mut sink)?;
+                self.finished = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the end marker unless it was written already, and return the
+    /// underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        if !self.finished {
+            self.write_token(Token::End)?;
+        }
+        Ok(self.writer)
+    }
+}
@@ -74,12 +74,17 @@ pub mod async_stream;
 
 // Re-export commonly used types
 pub use common::{
-    CompressionHeader, CompressionMode, CompressionStats, DictionarySize, PkLibError, Result,
+    CompressionHeader, CompressionLevel, CompressionMode, CompressionStats, DictionarySize,
+    PkLibError, Result, StatsLevel, Status, StreamPosition, TokenHistograms, TokenKind,
     MAX_REP_LENGTH, MAX_WINDOW_SIZE,
 };
 pub use crc32::{crc32, crc32_pklib};
-pub use explode::{explode_mpq_bytes, ExplodeReader};
-pub use implode::ImplodeWriter;
+pub use explode::{
+    explode_into, explode_mpq_bytes, explode_multi, explode_multi_limited,
+    explode_multi_with_members, ExplodeDecoder, ExplodeMember, ExplodeOptions, ExplodeReader,
+    MultiExplodeReader, Token, TokenInfo, TokenReader,
+};
+pub use implode::{implode_bound, implode_into, ImplodeEncoder, ImplodeWriter, TokenEncoder};
 
 // Re-export async types when async feature is enabled
 #[cfg(feature = "async")]
@@ -112,6 +117,71 @@ pub fn implode_bytes(
     implode::implode_bytes(data, mode, dict_size)
 }
 
+/// Compress data using the PKWare implode algorithm at a given compression level
+///
+/// # Arguments
+/// * `data` - The data to compress
+/// * `mode` - Compression mode (Binary or ASCII)
+/// * `dict_size` - Dictionary size (1KB, 2KB, or 4KB)
+/// * `level` - Compression level (Fast, Default, or Best)
+///
+/// # Returns
+/// A vector containing the compressed data
+pub fn implode_bytes_with_level(
+    data: &[u8],
+    mode: CompressionMode,
+    dict_size: DictionarySize,
+    level: CompressionLevel,
+) -> Result<Vec<u8>> {
+    implode::implode_bytes_with_level(data, mode, dict_size, level)
+}
+
+/// Compress data using the PKWare implode algorithm, choosing the compression
+/// mode automatically
+///
+/// # Arguments
+/// * `data` - The data to compress
+/// * `dict_size` - Dictionary size (1KB, 2KB, or 4KB)
+///
+/// # Returns
+/// A vector containing the compressed data, in the mode chosen by
+/// `CompressionMode::detect`
+pub fn implode_bytes_auto(data: &[u8], dict_size: DictionarySize) -> Result<Vec<u8>> {
+    implode::implode_bytes_auto(data, dict_size)
+}
+
+/// Compress data with the mode and dictionary size giving the smallest output
+///
+/// # Arguments
+/// * `data` - The data to compress
+///
+/// # Returns
+/// The smallest compressed data that decompresses back to `data`, with the
+/// compression mode and dictionary size it was compressed with
+pub fn implode_best(data: &[u8]) -> Result<(Vec<u8>, CompressionMode, DictionarySize)> {
+    implode::implode_best(data)
+}
+
+/// Compress data using the PKWare implode algorithm, giving up once the output
+/// reaches a size limit
+///
+/// # Arguments
+/// * `data` - The data to compress
+/// * `mode` - Compression mode (Binary or ASCII)
+/// * `dict_size` - Dictionary size (1KB, 2KB, or 4KB)
+/// * `max_out` - Size the compressed data has to stay below
+///
+/// # Returns
+/// The compressed data, or `None` if it would be `max_out` bytes or larger
+pub fn implode_bounded(
+    data: &[u8],
+    mode: CompressionMode,
+    dict_size: DictionarySize,
+    max_out: usize,
+) -> Result<Option<Vec<u8>>> {
+    implode::implode_bounded(data, mode, dict_size, max_out)
+}
+
 /// Decompress data using the PKWare explode algorithm
 ///
 /// # Arguments
@@ -123,6 +193,66 @@ pub fn explode_bytes(data: &[u8]) -> Result<Vec<u8>> {
     explode::explode_bytes(data)
 }
 
+/// Decompress untrusted data using the PKWare explode algorithm
+///
+/// # Arguments
+/// * `data` - The compressed data
+/// * `max_output` - Maximum number of decompressed bytes
+///
+/// # Returns
+/// A vector containing the decompressed data, or
+/// `PkLibError::OutputLimitExceeded` if it would be larger than `max_output`
+pub fn explode_bytes_limited(data: &[u8], max_output: u64) -> Result<Vec<u8>> {
+    explode::explode_bytes_limited(data, max_output)
+}
+
+/// Decompress data whose uncompressed length is known in advance
+///
+/// # Arguments
+/// * `data` - The compressed data
+/// * `expected_len` - The exact length of the decompressed data
+///
+/// # Returns
+/// A vector containing the decompressed data, or
+/// `PkLibError::LengthMismatch` if the stream decodes to a different length
+pub fn explode_bytes_exact(data: &[u8], expected_len: usize) -> Result<Vec<u8>> {
+    explode::explode_bytes_exact(data, expected_len)
+}
+
+/// Decompress as much of damaged data as possible
+///
+/// # Arguments
+/// * `data` - The compressed data
+///
+/// # Returns
+/// The data decoded before the first fatal error, and that error if there was one
+pub fn explode_bytes_recover(data: &[u8]) -> (Vec<u8>, Option<PkLibError>) {
+    explode::explode_bytes_recover(data)
+}
+
+/// Decompress a PKWare stream that is followed by other data
+///
+/// # Arguments
+/// * `data` - The compressed stream, possibly followed by unrelated bytes
+///
+/// # Returns
+/// The decompressed data and the number of bytes the compressed stream occupied
+pub fn explode_bytes_with_consumed(data: &[u8]) -> Result<(Vec<u8>, usize)> {
+    explode::explode_bytes_with_consumed(data)
+}
+
+/// Decompress data using the PKWare explode algorithm with the given options
+///
+/// # Arguments
+/// * `data` - The compressed data
+/// * `options` - Decoding options, such as whether an end marker is required
+///
+/// # Returns
+/// A vector containing the decompressed data
+pub fn explode_bytes_with_options(data: &[u8], options: ExplodeOptions) -> Result<Vec<u8>> {
+    explode::explode_bytes_with_options(data, options)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,214 @@
+//! Tests for the async API
+//!
+//! Run with `cargo test --features async`.
+
+#![cfg(feature = "async")]
+
+use futures::TryStreamExt;
+use pklib::{
+    crc32_pklib, explode_bytes, explode_bytes_async, explode_bytes_async_limited, implode_bytes,
+    implode_bytes_async, AsyncExplodeReader, AsyncImplodeWriter, AsyncStreamProcessor,
+    CompressionMode, DictionarySize, ExplodeReader, ImplodeWriter, PkLibError, StreamOptions,
+};
+use std::io::{Cursor, Write};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+mod common;
+
+use common::random_bytes;
+
+/// Test async decompression against the PKLib reference files
+#[tokio::test]
+async fn test_async_decompression() -> Result<(), Box<dyn std::error::Error>> {
+    for name in ["small", "medium", "large", "binary"] {
+        let dir = std::path::Path::new("tests/pklib_compat/test_data");
+        let expected = std::fs::read(dir.join(format!("{name}.decomp")))?;
+        let compressed = std::fs::read(dir.join(format!("{name}.imploded")))?;
+
+        assert_eq!(explode_bytes_async(&compressed).await?, expected);
+
+        // Small buffers split both the input and the output
+        let mut reader = AsyncExplodeReader::with_buffer_size(Cursor::new(&compressed), 7)?;
+        let mut decompressed = Vec::new();
+        while let Some(chunk) = reader.try_next().await? {
+            assert!(chunk.len() <= 7);
+            decompressed.extend_from_slice(&chunk);
+        }
+        assert_eq!(decompressed, expected);
+
+        // A truncated stream is an error
+        let truncated = &compressed[..compressed.len() - 1];
+        assert!(explode_bytes_async(truncated).await.is_err());
+    }
+
+    Ok(())
+}
+
+/// Test the output limit of the async reader
+#[tokio::test]
+async fn test_async_output_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let data = vec![0u8; 100_000];
+    let compressed = implode_bytes(&data, CompressionMode::Binary, DictionarySize::Size4K)?;
+
+    let reader = AsyncExplodeReader::new(Cursor::new(&compressed))?.with_limit(100_000);
+    let chunks: Vec<_> = reader.try_collect().await?;
+    assert_eq!(chunks.concat(), data);
+
+    let reader = AsyncExplodeReader::new(Cursor::new(&compressed))?.with_limit(99_999);
+    let result: Result<Vec<_>, _> = reader.try_collect().await;
+    assert!(matches!(
+        result,
+        Err(PkLibError::OutputLimitExceeded { limit: 99_999 })
+    ));
+
+    assert_eq!(
+        explode_bytes_async_limited(&compressed, 100_000).await?,
+        data
+    );
+    assert!(matches!(
+        explode_bytes_async_limited(&compressed, 99_999).await,
+        Err(PkLibError::OutputLimitExceeded { limit: 99_999 })
+    ));
+
+    // The data up to the limit is handed out before the error
+    let mut reader =
+        AsyncExplodeReader::with_buffer_size(Cursor::new(&compressed), 1000)?.with_limit(2500);
+    let mut output = Vec::new();
+    let error = loop {
+        match reader.try_next().await {
+            Ok(Some(chunk)) => output.extend_from_slice(&chunk),
+            Ok(None) => panic!("The output limit was not enforced"),
+            Err(error) => break error,
+        }
+    };
+    assert!(matches!(
+        error,
+        PkLibError::OutputLimitExceeded { limit: 2500 }
+    ));
+    assert_eq!(output, &data[..2500]);
+
+    Ok(())
+}
+
+/// Test streams whose last repetition runs past the end of a 4 KB block
+#[tokio::test]
+async fn test_async_block_boundary() -> Result<(), Box<dyn std::error::Error>> {
+    for blocks in 1..=3 {
+        let mut data = random_bytes(blocks * 4096 - 6, 0x2F6B_1A57);
+        let last = data[data.len() - 1];
+        data.extend_from_slice(&[last; 10]);
+        let compressed = implode_bytes(&data, CompressionMode::Binary, DictionarySize::Size4K)?;
+
+        assert_eq!(explode_bytes_async(&compressed).await?, data);
+
+        let len = data.len() as u64;
+        assert_eq!(explode_bytes_async_limited(&compressed, len).await?, data);
+        assert!(matches!(
+            explode_bytes_async_limited(&compressed, len - 1).await,
+            Err(PkLibError::OutputLimitExceeded { .. })
+        ));
+    }
+
+    Ok(())
+}
+
+/// Test that async compression produces the same stream as ImplodeWriter
+#[tokio::test]
+async fn test_async_compression() -> Result<(), Box<dyn std::error::Error>> {
+    let data = std::fs::read("tests/pklib_compat/test_data/large.decomp")?;
+    for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
+        let expected = implode_bytes(&data, mode, DictionarySize::Size4K)?;
+        assert_eq!(
+            implode_bytes_async(&data, mode, DictionarySize::Size4K).await?,
+            expected
+        );
+
+        // Small chunks and a small buffer split both the input and the output
+        let mut compressed = Vec::new();
+        let mut writer =
+            AsyncImplodeWriter::with_buffer_size(&mut compressed, mode, DictionarySize::Size4K, 7)?;
+        for chunk in data.chunks(1000) {
+            writer.write_chunk(chunk).await?;
+        }
+        writer.finish().await?;
+        assert_eq!(compressed, expected);
+        assert_eq!(explode_bytes(&compressed)?, data);
+
+        // Through AsyncWrite, shutting down writes the end of the stream
+        let mut compressed = Vec::new();
+        let mut writer =
+            AsyncImplodeWriter::with_buffer_size(&mut compressed, mode, DictionarySize::Size4K, 7)?;
+        writer.write_all(&data).await?;
+        writer.shutdown().await?;
+        assert_eq!(compressed, expected);
+
+        // A slow reader on the other end makes the writes wait
+        let (client, mut server) = tokio::io::duplex(64);
+        let mut writer = AsyncImplodeWriter::new(client, mode, DictionarySize::Size4K)?;
+        let write = async {
+            for chunk in data.chunks(1000) {
+                writer.write_all(chunk).await?;
+                AsyncWriteExt::flush(&mut writer).await?;
+            }
+            writer.shutdown().await
+        };
+        let mut compressed = Vec::new();
+        let (written, read) = tokio::join!(write, server.read_to_end(&mut compressed));
+        written?;
+        read?;
+        assert_eq!(compressed, expected);
+    }
+
+    Ok(())
+}
+
+/// Test the statistics reported by the async compressor and decompressor
+#[tokio::test]
+async fn test_async_stats() -> Result<(), Box<dyn std::error::Error>> {
+    let data = std::fs::read("tests/pklib_compat/test_data/large.decomp")?;
+    let expected = implode_bytes(&data, CompressionMode::ASCII, DictionarySize::Size4K)?;
+
+    // The async writer produces the same stream as ImplodeWriter, and the
+    // same statistics
+    let mut output = Vec::new();
+    let mut writer =
+        ImplodeWriter::new(&mut output, CompressionMode::ASCII, DictionarySize::Size4K)?;
+    writer.write_all(&data)?;
+    writer.try_finish()?;
+    let writer_stats = writer.stats();
+    drop(writer);
+
+    let mut compressed = Vec::new();
+    let stats = AsyncStreamProcessor::process_stream(
+        Cursor::new(&data),
+        &mut compressed,
+        CompressionMode::ASCII,
+        DictionarySize::Size4K,
+        StreamOptions::low_memory(),
+    )
+    .await?;
+    assert_eq!(compressed, expected);
+    assert_eq!(stats.input_bytes, data.len() as u64);
+    assert_eq!(stats.output_bytes, expected.len() as u64);
+    assert_eq!(stats.crc32, crc32_pklib(&data, 0));
+    assert_eq!(stats.literal_count, writer_stats.literal_count);
+    assert_eq!(stats.match_count, writer_stats.match_count);
+    assert_eq!(stats.compression_ratio, writer_stats.compression_ratio);
+
+    // The async reader counts the same tokens as ExplodeReader
+    let mut reader = ExplodeReader::new(&expected[..])?;
+    std::io::copy(&mut reader, &mut std::io::sink())?;
+    let reader_stats = reader.stats();
+
+    let mut reader = AsyncExplodeReader::with_buffer_size(Cursor::new(&expected), 1000)?;
+    while reader.try_next().await?.is_some() {}
+    let stats = reader.stats();
+    assert_eq!(stats.input_bytes, expected.len() as u64);
+    assert_eq!(stats.output_bytes, data.len() as u64);
+    assert_eq!(stats.crc32, reader_stats.crc32);
+    assert_eq!(stats.literal_count, reader_stats.literal_count);
+    assert_eq!(stats.match_count, reader_stats.match_count);
+    assert_eq!(stats.longest_match, reader_stats.longest_match);
+
+    Ok(())
+}
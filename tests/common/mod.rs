@@ -0,0 +1,88 @@
+//! Test data generators and helpers shared by the integration tests
+
+#![allow(dead_code)]
+
+use pklib::{CompressionMode, DictionarySize, ExplodeReader, ImplodeWriter, PkLibError};
+use std::io::{Read, Write};
+
+/// Generate deterministic text-like data with plenty of medium-range repetitions
+pub fn generate_text(len: usize, seed: u32) -> Vec<u8> {
+    const WORDS: [&[u8]; 12] = [
+        b"the ",
+        b"quick ",
+        b"brown ",
+        b"fox ",
+        b"jumps ",
+        b"over ",
+        b"lazy ",
+        b"dog ",
+        b"archive ",
+        b"sector ",
+        b"implode ",
+        b"explode\n",
+    ];
+
+    let mut state = seed;
+    let mut data = Vec::with_capacity(len + 16);
+    while data.len() < len {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        data.extend_from_slice(WORDS[(state >> 16) as usize % WORDS.len()]);
+        if (state >> 8).is_multiple_of(7) {
+            data.push((state >> 24) as u8);
+        }
+    }
+    data.truncate(len);
+    data
+}
+
+/// Generate a deterministic xorshift sequence, for data that doesn't compress
+pub fn xorshift(seed: u32) -> impl Iterator<Item = u32> {
+    let mut state = seed;
+    std::iter::repeat_with(move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    })
+}
+
+/// Generate deterministic random bytes
+pub fn random_bytes(len: usize, seed: u32) -> Vec<u8> {
+    xorshift(seed).take(len).map(|state| state as u8).collect()
+}
+
+/// Generate deterministic binary data with few and short repetitions
+pub fn generate_binary(len: usize) -> Vec<u8> {
+    (0..len as u32).map(|i| (i * i % 251) as u8).collect()
+}
+
+/// The `PkLibError` a reader reported wrapped in an `io::Error`
+pub fn reader_error(error: std::io::Error) -> Option<Box<PkLibError>> {
+    error
+        .into_inner()
+        .and_then(|error| error.downcast::<PkLibError>().ok())
+}
+
+/// Compress with a preset dictionary
+pub fn implode_with_dictionary(
+    data: &[u8],
+    mode: CompressionMode,
+    dict_size: DictionarySize,
+    dictionary: &[u8],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut writer =
+        ImplodeWriter::new(Vec::new(), mode, dict_size)?.with_dictionary(dictionary)?;
+    writer.write_all(data)?;
+    Ok(writer.finish()?)
+}
+
+/// Decompress with a preset dictionary
+pub fn explode_with_dictionary(
+    data: &[u8],
+    dictionary: &[u8],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut reader = ExplodeReader::new(data)?.with_dictionary(dictionary)?;
+    let mut output = Vec::new();
+    reader.read_to_end(&mut output)?;
+    Ok(output)
+}
@@ -3,8 +3,21 @@
 //! These tests verify that our compression implementation produces output
 //! that is compatible with the original PKLib implementation.
 
-use pklib::{explode_bytes, implode_bytes, CompressionMode, DictionarySize, ImplodeWriter};
-use std::io::Write;
+use pklib::implode::ImplodeState;
+use pklib::tables::LEN_BASE;
+use pklib::{
+    crc32, explode_bytes, implode_best, implode_bound, implode_bounded, implode_bytes,
+    implode_bytes_auto, implode_bytes_with_level, implode_into, CompressionLevel, CompressionMode,
+    DictionarySize, ExplodeReader, ImplodeEncoder, ImplodeWriter, PkLibError, StatsLevel, Status,
+    Token, TokenEncoder, TokenHistograms, TokenReader,
+};
+use std::io::{Read, Write};
+
+mod common;
+
+use common::{
+    explode_with_dictionary, generate_binary, generate_text, implode_with_dictionary, random_bytes,
+};
 
 /// Test basic compression functionality
 #[test]
@@ -116,19 +129,11 @@ fn test_repetitive_data() -> Result<(), Box<dyn std::error::Error>> {
 /// Test empty and small data compression
 #[test]
 fn test_edge_cases() -> Result<(), Box<dyn std::error::Error>> {
-    // Test empty data (compression works, decompression has edge case)
+    // Test empty data
     let empty_data = b"";
     let compressed = implode_bytes(empty_data, CompressionMode::Binary, DictionarySize::Size2K)?;
-    // TODO: Fix empty data decompression edge case
-    match explode_bytes(&compressed) {
-        Ok(decompressed) => assert_eq!(empty_data, &decompressed[..]),
-        Err(_) => {
-            // Known edge case: empty data decompression fails
-            println!(
-                "  Empty data decompression edge case - compression works, decompression needs fix"
-            );
-        }
-    }
+    let decompressed = explode_bytes(&compressed)?;
+    assert_eq!(empty_data, &decompressed[..]);
 
     // Test single byte
     let single_byte = b"X";
@@ -227,3 +232,642 @@ fn test_max_repetition_length() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// Test that streaming output does not depend on how the input is split into writes
+#[test]
+fn test_streaming_chunk_invariance() -> Result<(), Box<dyn std::error::Error>> {
+    let test_data = generate_text(256 * 1024, 0x1234_5678);
+
+    for dict_size in [
+        DictionarySize::Size1K,
+        DictionarySize::Size2K,
+        DictionarySize::Size4K,
+    ] {
+        let one_shot = implode_bytes(&test_data, CompressionMode::ASCII, dict_size)?;
+
+        for chunk_size in [1, 7, 4096, 4097, 65536] {
+            let mut writer = ImplodeWriter::new(Vec::new(), CompressionMode::ASCII, dict_size)?;
+            for chunk in test_data.chunks(chunk_size) {
+                writer.write_all(chunk)?;
+            }
+            let streamed = writer.finish()?;
+
+            assert_eq!(
+                one_shot, streamed,
+                "Output differs for {dict_size:?} with {chunk_size}-byte writes"
+            );
+        }
+
+        // Bounded window compression should still find plenty of repetitions
+        assert!(one_shot.len() < test_data.len() / 2);
+    }
+
+    Ok(())
+}
+
+/// Test that repetitions are found across block and write() boundaries
+#[test]
+fn test_matches_across_write_boundaries() -> Result<(), Box<dyn std::error::Error>> {
+    // Incompressible on its own, so any savings must come from back-references
+    let block = random_bytes(3000, 0xDEAD_BEEF);
+
+    let single = implode_bytes(&block, CompressionMode::Binary, DictionarySize::Size4K)?;
+
+    // The second copy straddles the first 4096-byte block boundary
+    let mut writer =
+        ImplodeWriter::new(Vec::new(), CompressionMode::Binary, DictionarySize::Size4K)?;
+    writer.write_all(&block)?;
+    writer.write_all(&block)?;
+    let doubled = writer.finish()?;
+
+    // The repeated copy should cost only a handful of 516-byte matches
+    assert!(
+        doubled.len() < single.len() + 64,
+        "Second copy was not matched: {} vs {} bytes",
+        doubled.len(),
+        single.len()
+    );
+
+    Ok(())
+}
+
+/// Test that every compression level produces decodable streams
+#[test]
+fn test_compression_levels() -> Result<(), Box<dyn std::error::Error>> {
+    let text = generate_text(64 * 1024, 42);
+    let binary = generate_binary(64 * 1024);
+
+    for data in [&text, &binary] {
+        for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
+            for dict_size in [
+                DictionarySize::Size1K,
+                DictionarySize::Size2K,
+                DictionarySize::Size4K,
+            ] {
+                let default = implode_bytes(data, mode, dict_size)?;
+                let mut sizes = Vec::new();
+
+                for level in [
+                    CompressionLevel::Fast,
+                    CompressionLevel::Default,
+                    CompressionLevel::Best,
+                ] {
+                    let compressed = implode_bytes_with_level(data, mode, dict_size, level)?;
+                    assert_eq!(
+                        &explode_bytes(&compressed)?,
+                        data,
+                        "Round trip failed at {level:?} ({mode:?}, {dict_size:?})"
+                    );
+
+                    // The default level is what implode_bytes uses
+                    if level == CompressionLevel::Default {
+                        assert_eq!(compressed, default);
+                    }
+                    sizes.push(compressed.len());
+                }
+
+                // Fast output is usually larger than Default, but not always:
+                // skipping lazy matching occasionally pays off
+                assert!(
+                    sizes[2] <= sizes[1],
+                    "Best larger than Default ({mode:?}, {dict_size:?}): {sizes:?}"
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Test compression with a preset dictionary
+#[test]
+fn test_preset_dictionary() -> Result<(), Box<dyn std::error::Error>> {
+    let text = generate_text(24 * 1024, 7);
+    let (dictionary, data) = text.split_at(8 * 1024);
+
+    for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
+        for dict_size in [
+            DictionarySize::Size1K,
+            DictionarySize::Size2K,
+            DictionarySize::Size4K,
+        ] {
+            // Dictionaries shorter and longer than the window, and inputs
+            // spanning several blocks
+            for dict_len in [1, 100, 1000, 4096, 8192] {
+                let preset = &dictionary[dictionary.len() - dict_len..];
+                for len in [1, 300, 4097, data.len()] {
+                    let compressed =
+                        implode_with_dictionary(&data[..len], mode, dict_size, preset)?;
+                    assert_eq!(
+                        explode_with_dictionary(&compressed, preset)?,
+                        &data[..len],
+                        "Round trip failed ({mode:?}, {dict_size:?}, {dict_len}, {len})"
+                    );
+                }
+            }
+
+            // Small records compress much better with shared content
+            let record = &data[..300];
+            let primed = implode_with_dictionary(record, mode, dict_size, dictionary)?;
+            let plain = implode_bytes(record, mode, dict_size)?;
+            assert!(primed.len() < plain.len() * 3 / 4);
+            assert_ne!(explode_bytes(&primed).ok().as_deref(), Some(record));
+
+            // An empty dictionary changes nothing
+            assert_eq!(
+                implode_with_dictionary(record, mode, dict_size, &[])?,
+                plain
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Test that the dictionary can't be set once data has been compressed
+#[test]
+fn test_preset_dictionary_too_late() -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer =
+        ImplodeWriter::new(Vec::new(), CompressionMode::Binary, DictionarySize::Size1K)?;
+    writer.write_all(b"data")?;
+    assert!(writer.with_dictionary(b"dictionary").is_err());
+
+    Ok(())
+}
+
+/// Test compression into a caller-provided buffer
+#[test]
+fn test_implode_into() -> Result<(), Box<dyn std::error::Error>> {
+    let text = generate_text(20_000, 3);
+
+    for data in [&b""[..], b"A", &text[..4096], &text] {
+        for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
+            let expected = implode_bytes(data, mode, DictionarySize::Size4K)?;
+
+            let mut buffer = vec![0u8; expected.len() + 100];
+            let written = implode_into(data, mode, DictionarySize::Size4K, &mut buffer)?;
+            assert_eq!(&buffer[..written], expected.as_slice());
+
+            let written = implode_into(
+                data,
+                mode,
+                DictionarySize::Size4K,
+                &mut buffer[..expected.len()],
+            )?;
+            assert_eq!(written, expected.len());
+
+            assert!(matches!(
+                implode_into(
+                    data,
+                    mode,
+                    DictionarySize::Size4K,
+                    &mut buffer[..expected.len() - 1]
+                ),
+                Err(PkLibError::BufferTooSmall)
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Compress `data` with a push encoder, passing input in `in_chunk` byte
+/// pieces and collecting output through an `out_chunk` byte buffer
+fn push_compress(
+    data: &[u8],
+    level: CompressionLevel,
+    in_chunk: usize,
+    out_chunk: usize,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut encoder =
+        ImplodeEncoder::with_level(CompressionMode::ASCII, DictionarySize::Size2K, level)?;
+    let mut output = Vec::new();
+    let mut buffer = vec![0u8; out_chunk];
+
+    for chunk in data.chunks(in_chunk) {
+        let mut input = chunk;
+        loop {
+            let (consumed, produced, status) = encoder.compress(input, &mut buffer)?;
+            input = &input[consumed..];
+            output.extend_from_slice(&buffer[..produced]);
+            if status == Status::GetInput {
+                assert!(input.is_empty());
+                break;
+            }
+            assert_eq!(status, Status::Ok);
+        }
+    }
+
+    loop {
+        let (produced, status) = encoder.finish(&mut buffer)?;
+        output.extend_from_slice(&buffer[..produced]);
+        if status == Status::StreamEnd {
+            break;
+        }
+    }
+    assert!(encoder.is_finished());
+
+    Ok(output)
+}
+
+/// Test that the push encoder produces the same stream as ImplodeWriter
+#[test]
+fn test_implode_encoder() -> Result<(), Box<dyn std::error::Error>> {
+    // Random bytes are the worst case for ASCII mode, text the typical one
+    let random = random_bytes(50_000, 0x9E37_79B9);
+    let text = b"It was the best of times, it was the worst of times. ".repeat(800);
+
+    for data in [&random[..], &text[..], &[], b"x"] {
+        for level in [
+            CompressionLevel::Fast,
+            CompressionLevel::Default,
+            CompressionLevel::Best,
+        ] {
+            let expected = implode_bytes_with_level(
+                data,
+                CompressionMode::ASCII,
+                DictionarySize::Size2K,
+                level,
+            )?;
+
+            for (in_chunk, out_chunk) in [(1, 1), (4096, 3), (7, 65536), (1 << 20, 1 << 20)] {
+                let compressed = push_compress(data, level, in_chunk, out_chunk)?;
+                assert_eq!(
+                    compressed, expected,
+                    "Push encoder mismatch at {level:?} with {in_chunk}-byte input, {out_chunk}-byte output"
+                );
+            }
+        }
+    }
+
+    // Compression cannot continue after finishing
+    let mut encoder = ImplodeEncoder::new(CompressionMode::Binary, DictionarySize::Size1K)?;
+    let mut buffer = [0u8; 16];
+    encoder.finish(&mut buffer)?;
+    assert!(encoder.compress(b"more", &mut buffer).is_err());
+
+    Ok(())
+}
+
+/// Test writing compressed streams token by token
+#[test]
+fn test_token_encoder() -> Result<(), Box<dyn std::error::Error>> {
+    // A custom parse: a literal run followed by a long overlapping repetition
+    let mut encoder =
+        TokenEncoder::new(Vec::new(), CompressionMode::ASCII, DictionarySize::Size1K)?;
+    for &byte in b"abc" {
+        encoder.write_token(Token::Literal(byte))?;
+    }
+    encoder.write_token(Token::Match {
+        length: 516,
+        distance: 3,
+    })?;
+    let compressed = encoder.finish()?;
+    assert_eq!(explode_bytes(&compressed)?, b"abc".repeat(173)[..519]);
+
+    // Invalid tokens are rejected
+    let mut encoder =
+        TokenEncoder::new(Vec::new(), CompressionMode::Binary, DictionarySize::Size1K)?;
+    for _ in 0..2000 {
+        encoder.write_token(Token::Literal(0))?;
+    }
+    for (length, distance) in [(1, 1), (517, 1)] {
+        assert!(matches!(
+            encoder.write_token(Token::Match { length, distance }),
+            Err(PkLibError::InvalidLength(_))
+        ));
+    }
+    for (length, distance) in [(3, 0), (2, 257), (3, 1025)] {
+        assert!(matches!(
+            encoder.write_token(Token::Match { length, distance }),
+            Err(PkLibError::InvalidDistance(_))
+        ));
+    }
+    encoder.write_token(Token::Match {
+        length: 2,
+        distance: 256,
+    })?;
+    encoder.write_token(Token::Match {
+        length: 3,
+        distance: 1024,
+    })?;
+    encoder.write_token(Token::End)?;
+    assert!(encoder.write_token(Token::Literal(0)).is_err());
+    assert_eq!(explode_bytes(&encoder.finish()?)?, vec![0; 2005]);
+
+    // Repetitions can't reach before the data, unless there is a dictionary
+    let mut encoder =
+        TokenEncoder::new(Vec::new(), CompressionMode::Binary, DictionarySize::Size1K)?;
+    encoder.write_token(Token::Literal(b'a'))?;
+    assert!(matches!(
+        encoder.write_token(Token::Match {
+            length: 2,
+            distance: 2
+        }),
+        Err(PkLibError::InvalidDistance(2))
+    ));
+    let mut encoder =
+        TokenEncoder::new(Vec::new(), CompressionMode::Binary, DictionarySize::Size1K)?
+            .with_dictionary(b"xy")?;
+    encoder.write_token(Token::Match {
+        length: 2,
+        distance: 2,
+    })?;
+    let compressed = encoder.finish()?;
+    assert_eq!(explode_with_dictionary(&compressed, b"xy")?, b"xy");
+
+    Ok(())
+}
+
+/// Test the statistics reported while compressing and decompressing
+#[test]
+fn test_stats() -> Result<(), Box<dyn std::error::Error>> {
+    let text = generate_text(50_000, 5);
+
+    for data in [&b"A"[..], &[0u8; 10_000], &text] {
+        for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
+            let mut writer = ImplodeWriter::new(Vec::new(), mode, DictionarySize::Size2K)?;
+            writer.write_all(data)?;
+            writer.try_finish()?;
+            assert!(writer.write_all(b"more").is_err());
+            let stats = writer.stats();
+            let compressed = writer.finish()?;
+
+            assert_eq!(stats.input_bytes, data.len() as u64);
+            assert_eq!(stats.bytes_processed, data.len());
+            assert_eq!(stats.output_bytes, compressed.len() as u64);
+            assert_eq!(stats.crc32, crc32(data));
+            if !data.is_empty() {
+                let ratio = compressed.len() as f64 / data.len() as f64;
+                assert!((stats.compression_ratio - ratio).abs() < 1e-9);
+            }
+
+            // The token counts match the stream
+            let (mut literals, mut matches, mut longest) = (0, 0, 0);
+            for info in TokenReader::new(compressed.as_slice()) {
+                match info?.token {
+                    Token::Literal(_) => literals += 1,
+                    Token::Match { length, .. } => {
+                        matches += 1;
+                        longest = longest.max(length as usize);
+                    }
+                    Token::End => {}
+                }
+            }
+            assert_eq!(stats.literal_count, literals);
+            assert_eq!(stats.match_count, matches);
+            assert_eq!(stats.longest_match, longest);
+
+            // Decompression reports the same tokens and checksum
+            let mut reader = ExplodeReader::new(compressed.as_slice())?;
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output)?;
+            let explode_stats = reader.stats();
+            assert_eq!(explode_stats.literal_count, literals);
+            assert_eq!(explode_stats.match_count, matches);
+            assert_eq!(explode_stats.longest_match, longest);
+            assert_eq!(explode_stats.input_bytes, compressed.len() as u64);
+            assert_eq!(explode_stats.output_bytes, data.len() as u64);
+            assert_eq!(explode_stats.crc32, stats.crc32);
+        }
+    }
+
+    // Statistics are live while the data is processed
+    let mut writer =
+        ImplodeWriter::new(Vec::new(), CompressionMode::ASCII, DictionarySize::Size4K)?;
+    writer.write_all(&text[..20_000])?;
+    let partial = writer.stats();
+    assert_eq!(partial.input_bytes, 20_000);
+    assert!(partial.literal_count + partial.match_count > 0);
+    writer.write_all(&text[20_000..])?;
+    let compressed = writer.finish()?;
+
+    let mut reader = ExplodeReader::new(compressed.as_slice())?;
+    let mut buffer = vec![0u8; 20_000];
+    reader.read_exact(&mut buffer)?;
+    let partial = reader.stats();
+    assert!(partial.output_bytes >= 20_000);
+    assert!(partial.input_bytes > 0 && partial.input_bytes < compressed.len() as u64);
+    assert_eq!(partial.crc32, crc32(&text[..partial.output_bytes as usize]));
+
+    Ok(())
+}
+
+/// Test the token histograms collected at `StatsLevel::Detailed`
+#[test]
+fn test_detailed_stats() -> Result<(), Box<dyn std::error::Error>> {
+    let text = generate_text(30_000, 9);
+    let binary = generate_binary(30_000);
+
+    for data in [&text, &binary] {
+        for (mode, dict_size) in [
+            (CompressionMode::ASCII, DictionarySize::Size1K),
+            (CompressionMode::Binary, DictionarySize::Size4K),
+        ] {
+            let mut writer = ImplodeWriter::new(Vec::new(), mode, dict_size)?
+                .with_stats_level(StatsLevel::Detailed);
+            writer.write_all(data)?;
+            writer.try_finish()?;
+            let stats = writer.stats();
+            let compressed = writer.finish()?;
+
+            // Histograms built from the tokens of the stream
+            let mut expected = TokenHistograms::default();
+            for info in TokenReader::new(compressed.as_slice()) {
+                match info?.token {
+                    Token::Literal(byte) => expected.literals[byte as usize] += 1,
+                    Token::Match { length, distance } => {
+                        let length_code = LEN_BASE
+                            .iter()
+                            .rposition(|&base| base <= length - 2)
+                            .ok_or("No length code")?;
+                        expected.length_codes[length_code] += 1;
+
+                        let low_bits = if length == 2 { 2 } else { dict_size.bits() };
+                        expected.distance_codes[(distance as usize - 1) >> low_bits] += 1;
+                    }
+                    Token::End => {}
+                }
+            }
+
+            let histograms = stats.histograms.as_deref().ok_or("No histograms")?;
+            assert_eq!(histograms, &expected);
+            assert_eq!(
+                histograms.literals.iter().sum::<usize>(),
+                stats.literal_count
+            );
+            assert_eq!(
+                histograms.length_codes.iter().sum::<usize>(),
+                stats.match_count
+            );
+
+            let mut reader =
+                ExplodeReader::new(compressed.as_slice())?.with_stats_level(StatsLevel::Detailed);
+            reader.read_to_end(&mut Vec::new())?;
+            assert_eq!(reader.stats().histograms.as_deref(), Some(&expected));
+
+            // Basic statistics leave them out
+            let mut reader = ExplodeReader::new(compressed.as_slice())?;
+            reader.read_to_end(&mut Vec::new())?;
+            assert!(reader.stats().histograms.is_none());
+        }
+    }
+
+    // Resetting the state restarts the statistics at the same level
+    let mut state = ImplodeState::new(CompressionMode::ASCII, DictionarySize::Size1K)?;
+    state.stats.histograms = Some(Box::default());
+    state.stats.literal_count = 10;
+    state.stats.input_bytes = 10;
+    state.reset();
+    assert_eq!(state.stats.literal_count, 0);
+    assert_eq!(state.stats.input_bytes, 0);
+    assert!(state.stats.histograms.is_some());
+
+    Ok(())
+}
+
+/// Test choosing the compression mode automatically
+#[test]
+fn test_detect_mode() -> Result<(), Box<dyn std::error::Error>> {
+    let text = generate_text(30_000, 4);
+    let binary = generate_binary(30_000);
+
+    for (data, expected_mode) in [
+        (&text, CompressionMode::ASCII),
+        (&binary, CompressionMode::Binary),
+    ] {
+        assert_eq!(CompressionMode::detect(data), expected_mode);
+
+        let compressed = implode_bytes_auto(data, DictionarySize::Size4K)?;
+        assert_eq!(
+            compressed,
+            implode_bytes(data, expected_mode, DictionarySize::Size4K)?
+        );
+        assert_eq!(explode_bytes(&compressed)?, *data);
+
+        // Streams choose from the part that is known up front
+        let mut writer =
+            ImplodeWriter::with_detected_mode(Vec::new(), &data[..1000], DictionarySize::Size4K)?;
+        assert_eq!(writer.mode(), expected_mode);
+        writer.write_all(data)?;
+        assert_eq!(writer.finish()?, compressed);
+    }
+
+    Ok(())
+}
+
+/// Test searching all settings for the smallest output
+#[test]
+fn test_implode_best() -> Result<(), Box<dyn std::error::Error>> {
+    let text = generate_text(20_000, 6);
+    let binary = generate_binary(20_000);
+
+    for data in [&b"A"[..], &text, &binary] {
+        let (compressed, mode, dict_size) = implode_best(data)?;
+        assert_eq!(explode_bytes(&compressed)?, data);
+        assert_eq!(
+            compressed,
+            implode_bytes_with_level(data, mode, dict_size, CompressionLevel::Best)?
+        );
+
+        for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
+            for dict_size in [
+                DictionarySize::Size1K,
+                DictionarySize::Size2K,
+                DictionarySize::Size4K,
+            ] {
+                let other =
+                    implode_bytes_with_level(data, mode, dict_size, CompressionLevel::Best)?;
+                assert!(compressed.len() <= other.len());
+            }
+        }
+    }
+
+    // Empty data compresses to the header and the end marker
+    let (compressed, _, _) = implode_best(b"")?;
+    assert_eq!(compressed.len(), 4);
+    assert_eq!(explode_bytes(&compressed)?, b"");
+
+    Ok(())
+}
+
+/// Test compression that gives up once the output reaches a limit
+#[test]
+fn test_implode_bounded() -> Result<(), Box<dyn std::error::Error>> {
+    let text = generate_text(20_000, 7);
+
+    for data in [&b"A"[..], &text[..4096], &text] {
+        for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
+            let expected = implode_bytes(data, mode, DictionarySize::Size2K)?;
+
+            let bounded = implode_bounded(data, mode, DictionarySize::Size2K, expected.len() + 1)?;
+            assert_eq!(bounded, Some(expected.clone()));
+
+            let bounded = implode_bounded(data, mode, DictionarySize::Size2K, expected.len())?;
+            assert_eq!(bounded, None);
+        }
+    }
+
+    // Random data doesn't shrink, so it is stored raw
+    let random = random_bytes(10_000, 0x2545_F491);
+    let bounded = implode_bounded(
+        &random,
+        CompressionMode::Binary,
+        DictionarySize::Size4K,
+        random.len(),
+    )?;
+    assert_eq!(bounded, None);
+
+    // Compression stops at the limit instead of at the next 2 KB flush
+    let mut state = ImplodeState::new(CompressionMode::Binary, DictionarySize::Size4K)?;
+    state.max_output = Some(100);
+    state.begin_output();
+    state.load_input(&random);
+    let mut flushed = 0;
+    let result = state.compress_block(false, &mut |bytes: &[u8]| {
+        flushed += bytes.len();
+        Ok(())
+    });
+    assert!(matches!(result, Err(PkLibError::BufferTooSmall)));
+    assert_eq!(flushed, 0);
+    assert_eq!(state.out_bytes + u32::from(state.out_bits > 0), 100);
+
+    Ok(())
+}
+
+/// Test the worst-case compressed size
+#[test]
+fn test_implode_bound() -> Result<(), Box<dyn std::error::Error>> {
+    let random = random_bytes(10_000, 0x9E37_79B9);
+    // Bytes with the longest (13-bit) ASCII codes
+    let rare: Vec<u8> = random.iter().map(|&byte| 0x80 | (byte & 0x1F)).collect();
+
+    for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
+        assert_eq!(
+            implode_bytes(b"", mode, DictionarySize::Size1K)?.len(),
+            implode_bound(0, mode)
+        );
+
+        for data in [&random, &rare] {
+            for level in [
+                CompressionLevel::Fast,
+                CompressionLevel::Default,
+                CompressionLevel::Best,
+            ] {
+                for dict_size in [DictionarySize::Size1K, DictionarySize::Size4K] {
+                    let compressed = implode_bytes_with_level(data, mode, dict_size, level)?;
+                    assert!(compressed.len() <= implode_bound(data.len(), mode));
+                }
+            }
+        }
+
+        // Lengths whose bound doesn't fit saturate instead of overflowing
+        assert_eq!(
+            implode_bound(usize::MAX / 8, mode),
+            usize::MAX.div_ceil(8) + 2
+        );
+        assert_eq!(implode_bound(usize::MAX, mode), usize::MAX.div_ceil(8) + 2);
+    }
+
+    Ok(())
+}
@@ -3,10 +3,24 @@
 //! This test suite verifies bit-for-bit compatibility with the original PKLib
 //! implementation by testing against reference compressed/decompressed file pairs.
 
-use pklib::{explode_bytes, implode_bytes, CompressionMode, DictionarySize};
+use pklib::{
+    crc32, explode_bytes, explode_bytes_exact, explode_bytes_limited, explode_bytes_recover,
+    explode_bytes_with_consumed, explode_bytes_with_options, explode_into, explode_multi,
+    explode_multi_limited, explode_multi_with_members, implode_bytes, CompressionMode,
+    DictionarySize, ExplodeDecoder, ExplodeOptions, ExplodeReader, MultiExplodeReader, PkLibError,
+    Status, StreamPosition, Token, TokenEncoder, TokenKind, TokenReader,
+};
 use std::fs;
+use std::io::Read;
 use std::path::Path;
 
+mod common;
+
+use common::{
+    explode_with_dictionary, generate_text, implode_with_dictionary, random_bytes, reader_error,
+    xorshift,
+};
+
 /// Test data directory containing PKLib reference files
 const TEST_DATA_DIR: &str = "tests/pklib_compat/test_data";
 
@@ -26,10 +40,8 @@ fn load_test_pair(name: &str) -> Result<(Vec<u8>, Vec<u8>), Box<dyn std::error::
 /// Test decompression compatibility with PKLib reference files
 #[test]
 fn test_decompression_compatibility() -> Result<(), Box<dyn std::error::Error>> {
-    let test_cases = vec![
-        "small", "medium", "large", "binary",
-        // TODO: Fix edge case - "no-explicit-end",
-    ];
+    // "no-explicit-end" lacks the end marker, see test_edge_cases
+    let test_cases = vec!["small", "medium", "large", "binary"];
 
     for test_case in test_cases {
         println!("Testing decompression: {test_case}");
@@ -83,6 +95,48 @@ fn test_ascii_decompression() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Test that our compression reproduces the PKLib reference files exactly
+#[test]
+fn test_compression_compatibility() -> Result<(), Box<dyn std::error::Error>> {
+    let test_cases = vec![
+        ("small", "small.imploded"),
+        ("medium", "medium.imploded"),
+        ("large", "large.imploded"),
+        ("binary", "binary.imploded"),
+        ("large", "large.imploded.ascii"),
+    ];
+
+    for (test_case, imploded_name) in test_cases {
+        println!("Testing compression: {imploded_name}");
+
+        let original_data = fs::read(Path::new(TEST_DATA_DIR).join(format!("{test_case}.decomp")))?;
+        let expected = fs::read(Path::new(TEST_DATA_DIR).join(imploded_name))?;
+
+        // The header tells which mode and dictionary size PKLib used
+        let mode = CompressionMode::from_u8(expected[0])?;
+        let dict_size = DictionarySize::from_bits(expected[1])?;
+
+        let compressed = implode_bytes(&original_data, mode, dict_size)
+            .map_err(|e| format!("Failed to compress {imploded_name}: {e}"))?;
+
+        assert_eq!(
+            expected, compressed,
+            "Compressed output differs from PKLib for: {imploded_name}"
+        );
+
+        println!(
+            "✓ {} compression verified ({:?}, {:?}, {} -> {} bytes)",
+            imploded_name,
+            mode,
+            dict_size,
+            original_data.len(),
+            compressed.len()
+        );
+    }
+
+    Ok(())
+}
+
 /// Test round-trip compression/decompression
 #[test]
 fn test_round_trip_compatibility() -> Result<(), Box<dyn std::error::Error>> {
@@ -179,6 +233,28 @@ fn test_compression_ratios() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Build a Binary mode stream of literals without an end marker, padding
+/// the last byte with zero bits
+fn binary_literals(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0, DictionarySize::Size1K.bits()];
+    let mut bits = 0u32;
+    let mut count = 0;
+    for &byte in data {
+        // A zero flag bit followed by the byte
+        bits |= (byte as u32) << (count + 1);
+        count += 9;
+        while count >= 8 {
+            stream.push(bits as u8);
+            bits >>= 8;
+            count -= 8;
+        }
+    }
+    if count > 0 {
+        stream.push(bits as u8);
+    }
+    stream
+}
+
 /// Stress test with edge cases
 #[test]
 fn test_edge_cases() -> Result<(), Box<dyn std::error::Error>> {
@@ -189,17 +265,121 @@ fn test_edge_cases() -> Result<(), Box<dyn std::error::Error>> {
     println!("Original: {} bytes", original.len());
     println!("Compressed: {} bytes", compressed.len());
 
-    // TODO: Fix no-explicit-end edge case decompression
-    match explode_bytes(&compressed) {
-        Ok(decompressed) => {
-            assert_eq!(original, decompressed);
-            println!("✓ Edge case verified");
-        }
-        Err(_) => {
-            println!("  no-explicit-end decompression edge case - needs investigation");
-        }
+    // The stream has no end marker, and it starts with repetitions of the
+    // zero-filled window before the data, so it only decodes when both are
+    // allowed
+    assert!(explode_bytes(&compressed).is_err());
+    let no_end_marker = ExplodeOptions {
+        require_end_marker: false,
+        ..ExplodeOptions::default()
+    };
+    assert!(matches!(
+        explode_bytes_with_options(&compressed, no_end_marker.clone())
+            .unwrap_err()
+            .root_cause(),
+        PkLibError::InvalidDistance(_)
+    ));
+    let lenient = ExplodeOptions {
+        require_end_marker: false,
+        strict_distances: false,
+        ..ExplodeOptions::default()
+    };
+
+    // Its last byte ends with a repetition PKLib drops, followed by a set bit
+    // that starts another token. So the stream is cut off in the middle of a
+    // token, and the repetition before it belongs to the data
+    let error = explode_bytes_with_options(&compressed, lenient.clone()).unwrap_err();
+    assert!(matches!(error.root_cause(), PkLibError::UnexpectedEof));
+    let position = error.position().ok_or("No position")?;
+    assert_eq!((position.byte_offset, position.bit_offset), (34, 7));
+    assert_eq!(position.output_offset, original.len() as u64 + 5);
+
+    let mut reader = ExplodeReader::with_options(
+        &compressed[..],
+        ExplodeOptions {
+            recover: true,
+            ..lenient.clone()
+        },
+    )?;
+    let mut decompressed = Vec::new();
+    assert!(reader.read_to_end(&mut decompressed).is_err());
+    assert_eq!(decompressed.len(), original.len() + 5);
+    assert!(decompressed.starts_with(&original));
+    println!("✓ Edge case verified");
+
+    // Streams with an end marker decode the same either way
+    for name in ["small", "binary"] {
+        let (expected, compressed) = load_test_pair(name)?;
+        assert_eq!(
+            expected,
+            explode_bytes_with_options(&compressed, lenient.clone())?
+        );
+
+        let mut reader = ExplodeReader::with_options(
+            ChunkedReader {
+                data: &compressed,
+                chunk: 1,
+            },
+            lenient.clone(),
+        )?;
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed)?;
+        assert_eq!(expected, decompressed);
     }
 
+    // Only padding may follow the last token; the input ending in the middle
+    // of a token is still an error
+    let (expected, compressed) = load_test_pair("small")?;
+    let error = explode_bytes_with_options(&compressed[..112], lenient.clone()).unwrap_err();
+    assert!(matches!(error.root_cause(), PkLibError::UnexpectedEof));
+    assert_eq!(error.position().map(|p| p.byte_offset), Some(111));
+
+    let mut reader = ExplodeReader::with_options(
+        ChunkedReader {
+            data: &compressed[..112],
+            chunk: 1,
+        },
+        lenient.clone(),
+    )?;
+    assert!(reader.read_to_end(&mut Vec::new()).is_err());
+
+    // Also when the last complete token ends within the final byte, and the
+    // rest of it holds the start of the next token
+    let error = explode_bytes_with_options(&compressed[..113], lenient.clone()).unwrap_err();
+    assert!(matches!(error.root_cause(), PkLibError::UnexpectedEof));
+    assert_eq!(error.position().map(|p| p.byte_offset), Some(112));
+    let mut reader = ExplodeReader::with_options(
+        &compressed[..113],
+        ExplodeOptions {
+            recover: true,
+            ..lenient.clone()
+        },
+    )?;
+    let mut decompressed = Vec::new();
+    assert!(reader.read_to_end(&mut decompressed).is_err());
+    assert!(expected.starts_with(&decompressed));
+
+    // A stream ending on a token boundary keeps its last token, also when
+    // zero bits pad it to a whole byte
+    let text = b"Literals without an end marker!!";
+    for len in [32, 31] {
+        let stream = binary_literals(&text[..len]);
+        assert_eq!(
+            explode_bytes_with_options(&stream, no_end_marker.clone())?,
+            &text[..len]
+        );
+    }
+
+    // Other bits after it can't be a complete token, so the input ended in
+    // the middle of one
+    let mut stream = binary_literals(&text[..31]);
+    *stream.last_mut().ok_or("Empty stream")? |= 0x80;
+    let error = explode_bytes_with_options(&stream, no_end_marker.clone()).unwrap_err();
+    assert!(matches!(error.root_cause(), PkLibError::UnexpectedEof));
+    let position = error.position().ok_or("No position")?;
+    assert_eq!((position.byte_offset, position.bit_offset), (36, 7));
+    assert_eq!(position.output_offset, 31);
+
     // Test very small files
     let small_data = b"Hi";
     for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
@@ -217,14 +397,48 @@ fn test_edge_cases() -> Result<(), Box<dyn std::error::Error>> {
     // Test empty data
     let empty_data = b"";
     let compressed = implode_bytes(empty_data, CompressionMode::Binary, DictionarySize::Size2K)?;
-    match explode_bytes(&compressed) {
-        Ok(decompressed) => assert_eq!(empty_data, &decompressed[..]),
-        Err(_) => {
-            println!("  Empty data decompression edge case - compression works");
+    let decompressed = explode_bytes(&compressed)?;
+    assert_eq!(empty_data, &decompressed[..]);
+
+    println!("✓ All edge cases passed");
+    Ok(())
+}
+
+/// Test decompressing the 4 byte stream of empty data
+///
+/// This deliberately differs from PKLib: its explode() wants more than 4
+/// bytes of input and rejects the stream its own implode() writes for empty
+/// data.
+#[test]
+fn test_empty_stream() -> Result<(), Box<dyn std::error::Error>> {
+    for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
+        for dict_size in [
+            DictionarySize::Size1K,
+            DictionarySize::Size2K,
+            DictionarySize::Size4K,
+        ] {
+            // The header, the first bit buffer byte and the end marker
+            let compressed = implode_bytes(b"", mode, dict_size)?;
+            assert_eq!(compressed.len(), 4);
+
+            assert_eq!(explode_bytes(&compressed)?, b"");
+            assert_eq!(explode_bytes_exact(&compressed, 0)?, b"");
+            assert_eq!(explode_into(&compressed, &mut [])?, 0);
+
+            let mut reader = ExplodeReader::new(&compressed[..])?;
+            let mut decompressed = Vec::new();
+            reader.read_to_end(&mut decompressed)?;
+            assert!(decompressed.is_empty());
+            assert_eq!(reader.consumed(), 4);
+
+            // Shorter input is still rejected
+            assert!(matches!(
+                explode_bytes(&compressed[..3]),
+                Err(PkLibError::InvalidData(_))
+            ));
         }
     }
 
-    println!("✓ All edge cases passed");
     Ok(())
 }
 
@@ -259,3 +473,701 @@ fn test_compression_modes() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// Reader that hands out at most `chunk` bytes per call, to exercise input refills
+struct ChunkedReader<'a> {
+    data: &'a [u8],
+    chunk: usize,
+}
+
+impl Read for ChunkedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = buf.len().min(self.chunk).min(self.data.len());
+        buf[..count].copy_from_slice(&self.data[..count]);
+        self.data = &self.data[count..];
+        Ok(count)
+    }
+}
+
+/// Build multi-megabyte test data out of the reference files, mutated so that
+/// repetitions of every length and distance cross the 4 KB output flush points
+fn build_large_input(size: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut sources = Vec::new();
+    for name in ["small", "medium", "large", "binary"] {
+        sources.push(load_test_pair(name)?.0);
+    }
+
+    let mut data = Vec::with_capacity(size);
+    for state in xorshift(0x2545_F491) {
+        if data.len() >= size {
+            break;
+        }
+
+        let source = &sources[state as usize % sources.len()];
+        let start = (state >> 8) as usize % source.len();
+        let len = ((state >> 20) as usize % 1500 + 1).min(source.len() - start);
+        data.extend_from_slice(&source[start..start + len]);
+        data.push((state >> 3) as u8);
+    }
+    data.truncate(size);
+    Ok(data)
+}
+
+/// Decompressed data together with its compressed stream
+type TestCase = (Vec<u8>, Vec<u8>);
+
+/// Reference file pairs together with a larger stream of generated data
+fn reference_cases() -> Result<Vec<TestCase>, Box<dyn std::error::Error>> {
+    let large = build_large_input(40_000)?;
+    let large_compressed = implode_bytes(&large, CompressionMode::Binary, DictionarySize::Size2K)?;
+
+    let mut cases = vec![(large, large_compressed)];
+    for test_case in ["small", "medium", "large", "binary"] {
+        cases.push(load_test_pair(test_case)?);
+    }
+    Ok(cases)
+}
+
+/// Test that the explode window is retained across output flushes on long
+/// streams, by round-tripping multi-megabyte data at every setting
+#[test]
+fn test_multi_megabyte_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    // There are no multi-megabyte PKLib references, so the streams come from
+    // implode_bytes. The data has repetitions of every length and distance
+    // crossing the 4 KB flush points.
+    let original = build_large_input(2 * 1024 * 1024)?;
+    assert_eq!(crc32(&original), 0x8454_2728);
+
+    for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
+        for dict_size in [
+            DictionarySize::Size1K,
+            DictionarySize::Size2K,
+            DictionarySize::Size4K,
+        ] {
+            let compressed = implode_bytes(&original, mode, dict_size)?;
+            let decompressed = explode_bytes(&compressed)
+                .map_err(|e| format!("Failed to decompress {mode:?}/{dict_size:?}: {e}"))?;
+
+            // Compare checksums, as printing megabytes of mismatching data
+            // helps no one
+            assert_eq!(
+                (decompressed.len(), crc32(&decompressed)),
+                (original.len(), crc32(&original)),
+                "Multi-megabyte mismatch for {mode:?}/{dict_size:?}"
+            );
+
+            println!(
+                "✓ {mode:?}/{dict_size:?} verified ({} -> {} bytes)",
+                original.len(),
+                compressed.len()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Test that decompression does not depend on how the input and output are chunked
+#[test]
+fn test_stream_chunking() -> Result<(), Box<dyn std::error::Error>> {
+    let original = build_large_input(256 * 1024)?;
+    let compressed = implode_bytes(&original, CompressionMode::Binary, DictionarySize::Size4K)?;
+
+    for (in_chunk, out_chunk) in [(1, 4096), (3, 1), (2047, 7), (4096, 65536)] {
+        let mut reader = ExplodeReader::new(ChunkedReader {
+            data: &compressed,
+            chunk: in_chunk,
+        })?;
+
+        let mut decompressed = Vec::new();
+        let mut buffer = vec![0u8; out_chunk];
+        loop {
+            let count = reader.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            decompressed.extend_from_slice(&buffer[..count]);
+        }
+
+        assert_eq!(
+            original, decompressed,
+            "Chunked decompression mismatch with {in_chunk}-byte input, {out_chunk}-byte output reads"
+        );
+    }
+
+    // The PKLib reference streams must also survive single-byte input reads
+    for name in ["small", "medium", "large", "binary"] {
+        let (expected, compressed) = load_test_pair(name)?;
+        let mut reader = ExplodeReader::new(ChunkedReader {
+            data: &compressed,
+            chunk: 1,
+        })?;
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed)?;
+        assert_eq!(expected, decompressed, "Chunked mismatch for {name}");
+    }
+
+    Ok(())
+}
+
+/// Test that decode errors report where in the stream they occurred
+#[test]
+fn test_error_position() -> Result<(), Box<dyn std::error::Error>> {
+    // The first token of this stream is a repetition of the window before
+    // the data: its length code starts at the first bit after the header
+    let (_, compressed) = load_test_pair("no-explicit-end")?;
+    let options = ExplodeOptions {
+        require_end_marker: false,
+        ..ExplodeOptions::default()
+    };
+    let error = explode_bytes_with_options(&compressed, options.clone()).unwrap_err();
+    assert!(matches!(error.root_cause(), PkLibError::InvalidDistance(_)));
+    let expected_position = StreamPosition {
+        byte_offset: 2,
+        bit_offset: 5,
+        output_offset: 0,
+        token: TokenKind::Distance,
+    };
+    assert_eq!(error.position(), Some(&expected_position));
+
+    // A reader keeps reporting the first error instead of decoding on from
+    // the broken bit state
+    let mut reader = ExplodeReader::with_options(&compressed[..], options)?;
+    let mut buffer = [0u8; 64];
+    for _ in 0..2 {
+        let error = reader_error(reader.read(&mut buffer).unwrap_err()).ok_or("No PkLibError")?;
+        assert_eq!(error.position(), Some(&expected_position));
+    }
+
+    // Truncated streams fail where the input runs out
+    let (expected, compressed) = load_test_pair("small")?;
+    for len in [5, 10, 100, compressed.len() - 1] {
+        let error = explode_bytes(&compressed[..len]).unwrap_err();
+        assert!(matches!(error.root_cause(), PkLibError::UnexpectedEof));
+
+        let position = *error.position().ok_or("No position")?;
+        assert!(position.byte_offset < len as u64);
+        assert!(position.output_offset <= expected.len() as u64);
+
+        // Everything before the failing token decodes
+        let mut reader = ExplodeReader::new(ChunkedReader {
+            data: &compressed[..len],
+            chunk: 1,
+        })?;
+        let mut output = Vec::new();
+        let mut buffer = [0u8; 1];
+        while let Ok(1) = reader.read(&mut buffer) {
+            output.push(buffer[0]);
+        }
+        assert!(output.len() as u64 <= position.output_offset);
+        assert_eq!(output, expected[..output.len()]);
+    }
+
+    // A token cut off after its first bit
+    let error = explode_bytes(&compressed[..5]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Unexpected end of input at byte 3 bit 1 (output offset 1) while decoding literal"
+    );
+
+    Ok(())
+}
+
+/// Test that damaged data decodes up to the damage
+#[test]
+fn test_recover_partial_output() -> Result<(), Box<dyn std::error::Error>> {
+    for (expected, compressed) in reference_cases()? {
+        // Intact data decodes completely
+        let (output, error) = explode_bytes_recover(&compressed);
+        assert_eq!(output, expected);
+        assert!(error.is_none());
+
+        // Truncated data keeps every token before the one cut off
+        for len in [4, 5, compressed.len() / 2, compressed.len() - 1] {
+            let (output, error) = explode_bytes_recover(&compressed[..len]);
+            let error = error.ok_or("No error")?;
+            assert_eq!(output, expected[..output.len()]);
+            if let Some(position) = error.position() {
+                assert_eq!(position.output_offset, output.len() as u64);
+            }
+
+            let options = ExplodeOptions {
+                recover: true,
+                ..ExplodeOptions::default()
+            };
+            let mut reader = ExplodeReader::with_options(
+                ChunkedReader {
+                    data: &compressed[..len],
+                    chunk: 3,
+                },
+                options,
+            )?;
+            let mut streamed = Vec::new();
+            assert!(reader.read_to_end(&mut streamed).is_err());
+            assert_eq!(streamed, output);
+        }
+    }
+
+    // Invalid repetitions stop decoding too
+    let (_, compressed) = load_test_pair("no-explicit-end")?;
+    let (output, error) = explode_bytes_recover(&compressed);
+    assert!(output.is_empty());
+    assert!(matches!(
+        error.as_ref().map(PkLibError::root_cause),
+        Some(PkLibError::InvalidDistance(_))
+    ));
+
+    Ok(())
+}
+
+/// Test inspecting the tokens of compressed streams
+#[test]
+fn test_token_reader() -> Result<(), Box<dyn std::error::Error>> {
+    for test_case in ["small", "medium", "large", "binary"] {
+        let (expected, compressed) = load_test_pair(test_case)?;
+
+        let mut reader = TokenReader::new(ChunkedReader {
+            data: &compressed,
+            chunk: 5,
+        });
+        assert!(reader.header().is_none());
+        let tokens = reader.by_ref().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            reader.header().map(|header| header.mode),
+            Some(CompressionMode::from_u8(compressed[0])?)
+        );
+        assert!(reader.next().is_none());
+
+        // The tokens expand to the data and cover the stream without gaps
+        let mut output = Vec::new();
+        let mut bit_offset = 16u64;
+        for info in &tokens {
+            assert_eq!(info.bit_offset, bit_offset);
+            bit_offset += info.bit_length as u64;
+
+            match info.token {
+                Token::Literal(byte) => output.push(byte),
+                Token::Match { length, distance } => {
+                    assert!((2..=516).contains(&length));
+                    for _ in 0..length {
+                        output.push(output[output.len() - distance as usize]);
+                    }
+                }
+                Token::End => {}
+            }
+        }
+        assert_eq!(output, expected);
+        assert_eq!(tokens.last().map(|info| info.token), Some(Token::End));
+        assert_eq!(bit_offset.div_ceil(8), compressed.len() as u64);
+    }
+
+    // Damaged streams end with the error
+    let (_, compressed) = load_test_pair("small")?;
+    let tokens: Vec<_> = TokenReader::new(&compressed[..compressed.len() / 2]).collect();
+    let error = tokens.last().ok_or("No tokens")?.as_ref().unwrap_err();
+    assert!(matches!(error.root_cause(), PkLibError::UnexpectedEof));
+    assert!(tokens[..tokens.len() - 1].iter().all(Result::is_ok));
+
+    Ok(())
+}
+
+/// Test that re-encoding the tokens of PKLib's output reproduces it exactly
+#[test]
+fn test_token_reencoding() -> Result<(), Box<dyn std::error::Error>> {
+    for test_case in ["small", "medium", "large", "binary"] {
+        let (_, compressed) = load_test_pair(test_case)?;
+        let mut tokens = TokenReader::new(compressed.as_slice());
+        let first = tokens.next().ok_or("No tokens")??;
+        let header = tokens.header().ok_or("No header")?;
+
+        let mut encoder = TokenEncoder::new(Vec::new(), header.mode, header.dict_size)?;
+        encoder.write_token(first.token)?;
+        for info in tokens {
+            encoder.write_token(info?.token)?;
+        }
+        assert_eq!(encoder.finish()?, compressed);
+    }
+
+    Ok(())
+}
+
+/// Test that repetitions reaching before the start of the data are rejected
+#[test]
+fn test_strict_distances() -> Result<(), Box<dyn std::error::Error>> {
+    let text = generate_text(8 * 1024 + 300, 7);
+    let (dictionary, record) = text.split_at(8 * 1024);
+
+    for mode in [CompressionMode::Binary, CompressionMode::ASCII] {
+        let primed = implode_with_dictionary(record, mode, DictionarySize::Size4K, dictionary)?;
+
+        // Without the dictionary, or with too little of it, the first
+        // repetition points before the start of the window
+        assert!(matches!(
+            explode_bytes(&primed).unwrap_err().root_cause(),
+            PkLibError::InvalidDistance(_)
+        ));
+        let short = &dictionary[dictionary.len() - 10..];
+        let mut reader = ExplodeReader::new(primed.as_slice())?.with_dictionary(short)?;
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(matches!(
+            reader_error(error).as_deref().map(PkLibError::root_cause),
+            Some(PkLibError::InvalidDistance(_))
+        ));
+        let mut decoder = ExplodeDecoder::new();
+        assert!(matches!(
+            decoder
+                .decompress(&primed, &mut [0u8; 1024])
+                .unwrap_err()
+                .root_cause(),
+            PkLibError::InvalidDistance(_)
+        ));
+
+        // PKLib just copies whatever the window held
+        let lenient = ExplodeOptions {
+            strict_distances: false,
+            ..ExplodeOptions::default()
+        };
+        let garbage = explode_bytes_with_options(&primed, lenient)?;
+        assert_eq!(garbage.len(), record.len());
+        assert_ne!(garbage, record);
+
+        assert_eq!(explode_with_dictionary(&primed, dictionary)?, record);
+    }
+
+    Ok(())
+}
+
+/// Test that the dictionary can't be set once data has been read
+#[test]
+fn test_preset_dictionary_too_late() -> Result<(), Box<dyn std::error::Error>> {
+    let compressed = implode_bytes(b"data", CompressionMode::Binary, DictionarySize::Size1K)?;
+    let mut reader = ExplodeReader::new(compressed.as_slice())?;
+    let mut buffer = [0u8; 2];
+    reader.read_exact(&mut buffer)?;
+    assert!(reader.with_dictionary(b"dictionary").is_err());
+
+    Ok(())
+}
+
+/// Test decompression into a caller-provided buffer
+#[test]
+fn test_explode_into() -> Result<(), Box<dyn std::error::Error>> {
+    for (expected, compressed) in reference_cases()? {
+        // Exactly the right size, and with room to spare
+        let mut buffer = vec![0u8; expected.len() + 100];
+        let written = explode_into(&compressed, &mut buffer[..expected.len()])?;
+        assert_eq!(&buffer[..written], expected.as_slice());
+
+        let written = explode_into(&compressed, &mut buffer)?;
+        assert_eq!(&buffer[..written], expected.as_slice());
+
+        // One byte short
+        assert!(matches!(
+            explode_into(&compressed, &mut buffer[..expected.len() - 1]),
+            Err(PkLibError::BufferTooSmall)
+        ));
+    }
+
+    Ok(())
+}
+
+/// Test decompression with a known uncompressed length
+#[test]
+fn test_explode_exact() -> Result<(), Box<dyn std::error::Error>> {
+    for (expected, compressed) in reference_cases()? {
+        let len = expected.len();
+        assert_eq!(explode_bytes_exact(&compressed, len)?, expected);
+
+        // The stream ends early
+        assert!(matches!(
+            explode_bytes_exact(&compressed, len + 1),
+            Err(PkLibError::LengthMismatch { expected, actual }) if expected == len + 1 && actual == len
+        ));
+
+        // The stream runs past the expected length
+        assert!(matches!(
+            explode_bytes_exact(&compressed, len - 1),
+            Err(PkLibError::LengthMismatch { expected, actual }) if expected == len - 1 && actual > len - 1
+        ));
+
+        // Decoding stops at the token that passes the expected length
+        assert!(matches!(
+            explode_bytes_exact(&compressed, 3),
+            Err(PkLibError::LengthMismatch { expected: 3, actual }) if actual > 3 && actual <= 3 + 516
+        ));
+    }
+
+    // A bogus length doesn't make it allocate more than the data can expand to
+    let (expected, compressed) = load_test_pair("small")?;
+    assert!(matches!(
+        explode_bytes_exact(&compressed, usize::MAX),
+        Err(PkLibError::LengthMismatch { actual, .. }) if actual == expected.len()
+    ));
+
+    Ok(())
+}
+
+/// Feed `input` to a push decoder in `in_chunk` byte pieces, with an output
+/// buffer of `out_chunk` bytes, returning the output and the input consumed
+fn push_decompress(
+    input: &[u8],
+    in_chunk: usize,
+    out_chunk: usize,
+) -> Result<(Vec<u8>, usize, Status), Box<dyn std::error::Error>> {
+    let mut decoder = ExplodeDecoder::new();
+    let mut output = Vec::new();
+    let mut buffer = vec![0u8; out_chunk];
+    let mut pos = 0;
+
+    loop {
+        let end = (pos + in_chunk).min(input.len());
+        let (consumed, produced, status) = decoder.decompress(&input[pos..end], &mut buffer)?;
+        pos += consumed;
+        output.extend_from_slice(&buffer[..produced]);
+
+        match status {
+            Status::Ok => assert_eq!(produced, out_chunk, "Ok without a full output buffer"),
+            Status::GetInput if end == input.len() => return Ok((output, pos, status)),
+            Status::GetInput => assert_eq!(pos, end, "GetInput with input left over"),
+            Status::StreamEnd => {
+                assert!(decoder.is_finished());
+                return Ok((output, pos, status));
+            }
+        }
+    }
+}
+
+/// Test the push-style decoder with input and output split at every size
+#[test]
+fn test_explode_decoder() -> Result<(), Box<dyn std::error::Error>> {
+    let large = build_large_input(128 * 1024)?;
+    let mut cases = Vec::new();
+    for (mode, dict_size) in [
+        (CompressionMode::Binary, DictionarySize::Size4K),
+        (CompressionMode::ASCII, DictionarySize::Size1K),
+    ] {
+        cases.push((large.clone(), implode_bytes(&large, mode, dict_size)?));
+    }
+    for test_case in ["small", "medium", "large", "binary"] {
+        cases.push(load_test_pair(test_case)?);
+    }
+
+    // The last repetition runs past the end of a 4 KB block, right before the
+    // end marker
+    for blocks in 1..=3 {
+        let mut data = random_bytes(blocks * 4096 - 6, 0x2F6B_1A57);
+        let last = data[data.len() - 1];
+        data.extend_from_slice(&[last; 10]);
+        let compressed = implode_bytes(&data, CompressionMode::Binary, DictionarySize::Size4K)?;
+        assert_eq!(explode_bytes(&compressed)?, data);
+        cases.push((data, compressed));
+    }
+
+    for (expected, compressed) in &cases {
+        for (in_chunk, out_chunk) in [
+            (1, 1),
+            (1, 65536),
+            (2, 4096),
+            (7, 3),
+            (65536, 1),
+            (1 << 20, 1 << 20),
+        ] {
+            let (output, consumed, status) = push_decompress(compressed, in_chunk, out_chunk)?;
+            assert_eq!(status, Status::StreamEnd);
+            assert_eq!(consumed, compressed.len());
+            assert_eq!(
+                expected, &output,
+                "Push decoder mismatch with {in_chunk}-byte input, {out_chunk}-byte output"
+            );
+        }
+
+        // Data after the end of the stream is left unconsumed
+        let mut followed = compressed.clone();
+        followed.extend_from_slice(b"trailing data");
+        for (in_chunk, out_chunk) in [(1, 1), (5, 4096), (1 << 20, 1 << 20)] {
+            let (output, consumed, status) = push_decompress(&followed, in_chunk, out_chunk)?;
+            assert_eq!(status, Status::StreamEnd);
+            assert_eq!(consumed, compressed.len());
+            assert_eq!(expected, &output);
+        }
+
+        // A truncated stream asks for more input
+        let (output, consumed, status) =
+            push_decompress(&compressed[..compressed.len() - 1], 1 << 20, 1 << 20)?;
+        assert_eq!(status, Status::GetInput);
+        assert_eq!(consumed, compressed.len() - 1);
+        assert!(expected.starts_with(&output));
+    }
+
+    Ok(())
+}
+
+/// Test that streams embedded in larger data report their exact length
+#[test]
+fn test_embedded_streams() -> Result<(), Box<dyn std::error::Error>> {
+    let trailer: Vec<u8> = (0..5000u32).map(|i| (i * 7 + 3) as u8).collect();
+
+    for test_case in ["small", "medium", "large", "binary"] {
+        let (expected, compressed) = load_test_pair(test_case)?;
+
+        for trailer_len in [0, 1, 2, 100, trailer.len()] {
+            let mut data = compressed.clone();
+            data.extend_from_slice(&trailer[..trailer_len]);
+
+            let (decompressed, consumed) = explode_bytes_with_consumed(&data)?;
+            assert_eq!(expected, decompressed);
+            assert_eq!(
+                consumed,
+                compressed.len(),
+                "{test_case} with {trailer_len} trailing bytes"
+            );
+
+            // The remainder and the rest of the inner reader are what followed
+            for chunk in [1, 3, 4096] {
+                let mut reader = ExplodeReader::new(ChunkedReader { data: &data, chunk })?;
+                let mut decompressed = Vec::new();
+                reader.read_to_end(&mut decompressed)?;
+                assert_eq!(expected, decompressed);
+                assert_eq!(reader.consumed(), compressed.len() as u64);
+
+                let (mut inner, mut following) = reader.into_inner_with_remainder();
+                inner.read_to_end(&mut following)?;
+                assert_eq!(following, &trailer[..trailer_len]);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Test decompression of several streams stored back to back
+#[test]
+fn test_concatenated_streams() -> Result<(), Box<dyn std::error::Error>> {
+    let mut members = Vec::new();
+    for test_case in ["small", "binary", "medium"] {
+        members.push(load_test_pair(test_case)?);
+    }
+    let text = b"Member compressed with a different mode and dictionary size. ".repeat(100);
+    members.push((
+        text.clone(),
+        implode_bytes(&text, CompressionMode::ASCII, DictionarySize::Size1K)?,
+    ));
+    members.push((
+        b"x".to_vec(),
+        implode_bytes(b"x", CompressionMode::Binary, DictionarySize::Size2K)?,
+    ));
+
+    let mut data = Vec::new();
+    let mut expected = Vec::new();
+    for (decompressed, compressed) in &members {
+        data.extend_from_slice(compressed);
+        expected.extend_from_slice(decompressed);
+    }
+
+    assert_eq!(explode_multi(&data)?, expected);
+
+    let (output, boundaries) = explode_multi_with_members(&data)?;
+    assert_eq!(output, expected);
+    assert_eq!(boundaries.len(), members.len());
+
+    let (mut compressed_offset, mut decompressed_offset) = (0, 0);
+    for (member, (decompressed, compressed)) in boundaries.iter().zip(&members) {
+        assert_eq!(member.compressed_offset, compressed_offset);
+        assert_eq!(member.compressed_len, compressed.len() as u64);
+        assert_eq!(member.decompressed_offset, decompressed_offset);
+        assert_eq!(member.decompressed_len, decompressed.len() as u64);
+        assert_eq!(member.mode, CompressionMode::from_u8(compressed[0])?);
+        assert_eq!(member.dict_size, DictionarySize::from_bits(compressed[1])?);
+        compressed_offset += member.compressed_len;
+        decompressed_offset += member.decompressed_len;
+    }
+
+    // Short reads from the input and into the output
+    let mut reader = MultiExplodeReader::new(ChunkedReader {
+        data: &data,
+        chunk: 1,
+    })?;
+    let mut output = Vec::new();
+    let mut buffer = [0u8; 7];
+    loop {
+        let count = reader.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        output.extend_from_slice(&buffer[..count]);
+    }
+    assert_eq!(output, expected);
+    assert_eq!(reader.members(), boundaries.as_slice());
+
+    // No members at all, and garbage after the last member
+    assert!(explode_multi(&[])?.is_empty());
+    data.push(0xFF);
+    assert!(explode_multi(&data).is_err());
+
+    Ok(())
+}
+
+/// Test that the output limit stops decompression bombs
+#[test]
+fn test_output_limit() -> Result<(), Box<dyn std::error::Error>> {
+    // 1 MB of zeros compresses to a few kilobytes
+    let bomb = vec![0u8; 1024 * 1024];
+    let compressed = implode_bytes(&bomb, CompressionMode::Binary, DictionarySize::Size4K)?;
+    let limit = bomb.len() as u64;
+
+    assert_eq!(explode_bytes_limited(&compressed, limit)?, bomb);
+    assert!(matches!(
+        explode_bytes_limited(&compressed, limit - 1),
+        Err(PkLibError::OutputLimitExceeded { limit: l }) if l == limit - 1
+    ));
+    assert!(matches!(
+        explode_bytes_limited(&compressed, 4096),
+        Err(PkLibError::OutputLimitExceeded { limit: 4096 })
+    ));
+
+    // The streaming reader stops the same way
+    let mut output = Vec::new();
+    ExplodeReader::new(compressed.as_slice())?
+        .with_limit(limit)
+        .read_to_end(&mut output)?;
+    assert_eq!(output, bomb);
+
+    let mut reader = ExplodeReader::new(compressed.as_slice())?.with_limit(limit - 1);
+    let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert!(matches!(
+        reader_error(error).as_deref(),
+        Some(PkLibError::OutputLimitExceeded { .. })
+    ));
+
+    // The members of concatenated streams share the limit
+    let bombs = [compressed.as_slice(), &compressed].concat();
+    assert_eq!(
+        explode_multi_limited(&bombs, 2 * limit)?,
+        [bomb.as_slice(), &bomb].concat()
+    );
+    assert!(matches!(
+        explode_multi_limited(&bombs, 2 * limit - 1),
+        Err(PkLibError::OutputLimitExceeded { limit: l }) if l == 2 * limit - 1
+    ));
+
+    let mut output = Vec::new();
+    let mut reader = MultiExplodeReader::new(bombs.as_slice())?.with_limit(limit + 10);
+    let error = reader.read_to_end(&mut output).unwrap_err();
+    assert!(matches!(
+        reader_error(error).as_deref(),
+        Some(PkLibError::OutputLimitExceeded { limit: l }) if *l == limit + 10
+    ));
+    assert_eq!(output.len() as u64, limit + 10);
+
+    // The data up to the limit is handed out before the error
+    let expected = build_large_input(64 * 1024)?;
+    let compressed = implode_bytes(&expected, CompressionMode::ASCII, DictionarySize::Size4K)?;
+    for limit in [0, 1, 1000, 4096, 4097, 10_000, expected.len() - 1] {
+        let mut output = Vec::new();
+        let mut reader = ExplodeReader::new(compressed.as_slice())?.with_limit(limit as u64);
+        assert!(reader.read_to_end(&mut output).is_err());
+        assert_eq!(output, &expected[..limit]);
+    }
+
+    Ok(())
+}